@@ -0,0 +1,499 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek as _, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use tar::{Archive, Builder, EntryType, Header};
+use walkdir::WalkDir;
+use xz2::{read::XzDecoder, write::XzEncoder};
+
+use crate::{
+    encryption,
+    error::{Error, Result},
+    hash::HashingReader,
+    manifest::{FileEntry, Manifest, MANIFEST_FILE_NAME},
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+/// The compression of an archive, with its level.
+#[derive(Clone, Copy)]
+pub enum Compression {
+    Gzip(u32),
+    Zstd(i32),
+    Xz(u32),
+}
+
+/// Writes a compressed tarball, and collects sizes and checksums of its files into the
+/// manifest, which is the last file of the tarball.
+pub(crate) struct ArchiveWriter {
+    builder: Builder<Encoder>,
+    manifest: Manifest,
+    /// Where spool files are created, it is the directory of the tarball.
+    spool_dir: PathBuf,
+}
+
+enum Encoder {
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+    Xz(XzEncoder<BufWriter<File>>),
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::Gzip(6)
+    }
+}
+
+impl Compression {
+    /// Creates the compression with the level, or the default level of the format.
+    pub(crate) fn new(format: &str, level: Option<u32>) -> std::result::Result<Self, String> {
+        let (compression, max_level) = match format {
+            "gzip" => (Self::Gzip(level.unwrap_or(6)), 9),
+            "zstd" => (Self::Zstd(level.unwrap_or(3) as i32), 19),
+            "xz" => (Self::Xz(level.unwrap_or(6)), 9),
+            _ => return Err(format!("unknown compression \"{}\"", format)),
+        };
+        match level {
+            Some(level) if level > max_level => Err(format!(
+                "the level of {} should be at most {}, but got {}",
+                format, max_level, level
+            )),
+            _ => Ok(compression),
+        }
+    }
+
+    /// The extension of file names of tarballs in this compression.
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Gzip(_) => "tar.gz",
+            Self::Zstd(_) => "tar.zst",
+            Self::Xz(_) => "tar.xz",
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Gzip(inner) => inner.write(buf),
+            Self::Zstd(inner) => inner.write(buf),
+            Self::Xz(inner) => inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Gzip(inner) => inner.flush(),
+            Self::Zstd(inner) => inner.flush(),
+            Self::Xz(inner) => inner.flush(),
+        }
+    }
+}
+
+impl Encoder {
+    fn finish(self) -> io::Result<()> {
+        let mut file = match self {
+            Self::Gzip(inner) => inner.finish()?,
+            Self::Zstd(inner) => inner.finish()?,
+            Self::Xz(inner) => inner.finish()?,
+        };
+        file.flush()
+    }
+}
+
+impl ArchiveWriter {
+    /// Creates the tarball, the manifest is written after all other files.
+    pub(crate) fn create(
+        path: &Path,
+        compression: Compression,
+        manifest: Manifest,
+    ) -> Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map(BufWriter::new)
+            .map_err(|err| {
+                let msg = format!("failed to create '{}' since {}", path.display(), err);
+                Error::Exec(msg)
+            })?;
+        let encoder = match compression {
+            Compression::Gzip(level) => {
+                Encoder::Gzip(GzEncoder::new(file, flate2::Compression::new(level)))
+            }
+            Compression::Zstd(level) => {
+                let encoder = zstd::Encoder::new(file, level).map_err(|err| {
+                    let msg = format!("failed to create zstd encoder since {}", err);
+                    Error::Exec(msg)
+                })?;
+                Encoder::Zstd(encoder)
+            }
+            Compression::Xz(level) => Encoder::Xz(XzEncoder::new(file, level)),
+        };
+        let builder = Builder::new(encoder);
+        let spool_dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+            _ => PathBuf::from("."),
+        };
+        Ok(Self {
+            builder,
+            manifest,
+            spool_dir,
+        })
+    }
+
+    /// Appends a file as `name`.
+    pub(crate) fn append_file(&mut self, name: &str, src_path: &Path) -> Result<()> {
+        let file = File::open(src_path).map_err(|err| {
+            let msg = format!("failed to open '{}' since {}", src_path.display(), err);
+            Error::Exec(msg)
+        })?;
+        let metadata = file.metadata().map_err(|err| {
+            let msg = format!("failed to stat '{}' since {}", src_path.display(), err);
+            Error::Exec(msg)
+        })?;
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&metadata, tar::HeaderMode::Complete);
+        // Files of a running node could be changed while archiving, such as the peer store, so
+        // exactly the size in the header is archived to keep the tarball valid.
+        let size = metadata.len();
+        let reader = BufReader::new(file)
+            .take(size)
+            .chain(io::repeat(0))
+            .take(size);
+        self.append(name, header, reader)
+    }
+
+    /// Appends all files under the directory, their names are prefixed by `name`.
+    pub(crate) fn append_dir(&mut self, name: &str, src_dir: &Path) -> Result<()> {
+        for entry in WalkDir::new(src_dir).sort_by_file_name() {
+            let entry = entry.map_err(|err| {
+                let msg = format!("failed to walk '{}' since {}", src_dir.display(), err);
+                Error::Exec(msg)
+            })?;
+            let relative_path = entry
+                .path()
+                .strip_prefix(src_dir)
+                .expect("walkdir returns paths under the root");
+            let entry_name = Path::new(name).join(relative_path);
+            let entry_name = entry_name.to_str().ok_or_else(|| {
+                let msg = format!("invalid file name '{}'", entry.path().display());
+                Error::Exec(msg)
+            })?;
+            if entry.file_type().is_dir() {
+                self.builder
+                    .append_dir(entry_name, entry.path())
+                    .map_err(|err| {
+                        let msg = format!("failed to archive '{}' since {}", entry_name, err);
+                        Error::Exec(msg)
+                    })?;
+            } else if entry.file_type().is_file() {
+                self.append_file(entry_name, entry.path())?;
+            } else {
+                log::warn!("skip '{}' since it is not a file", entry.path().display());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn append_bytes(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let header = new_header(data.len() as u64);
+        self.append(name, header, data)
+    }
+
+    /// Creates a temporary file next to the tarball, for data which is produced on the fly.
+    ///
+    /// The size of a file is required before its data in a tarball, so the data is written into
+    /// the spool file first, then archived by [`Self::append_spooled`]. The file is removed when
+    /// it is dropped.
+    pub(crate) fn spool(&self) -> Result<BufWriter<File>> {
+        tempfile::tempfile_in(&self.spool_dir)
+            .map(BufWriter::new)
+            .map_err(|err| {
+                let msg = format!(
+                    "failed to create a spool file in '{}' since {}",
+                    self.spool_dir.display(),
+                    err
+                );
+                Error::Exec(msg)
+            })
+    }
+
+    /// Appends the data in the spool file as `name`.
+    pub(crate) fn append_spooled(&mut self, name: &str, spool: BufWriter<File>) -> Result<()> {
+        let io_error = |err: io::Error| {
+            let msg = format!("failed to read the spool file of '{}' since {}", name, err);
+            Error::Exec(msg)
+        };
+        let mut file = spool
+            .into_inner()
+            .map_err(|err| io_error(err.into_error()))?;
+        let size = file.seek(SeekFrom::End(0)).map_err(io_error)?;
+        file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        let header = new_header(size);
+        self.append(name, header, BufReader::new(file))
+    }
+
+    /// Writes the manifest and finishes the tarball.
+    pub(crate) fn finish(mut self) -> Result<()> {
+        let data = serde_json::to_vec_pretty(&self.manifest).expect("serde_json::to_vec(..)");
+        let mut header = new_header(data.len() as u64);
+        self.builder
+            .append_data(&mut header, MANIFEST_FILE_NAME, data.as_slice())
+            .and_then(|_| self.builder.into_inner())
+            .and_then(Encoder::finish)
+            .map_err(|err| {
+                let msg = format!("failed to finish the archive since {}", err);
+                Error::Exec(msg)
+            })
+    }
+
+    fn append<R: Read>(&mut self, name: &str, mut header: Header, reader: R) -> Result<()> {
+        log::debug!("archive '{}'", name);
+        let mut reader = HashingReader::new(reader);
+        self.builder
+            .append_data(&mut header, name, &mut reader)
+            .map_err(|err| {
+                let msg = format!("failed to archive '{}' since {}", name, err);
+                Error::Exec(msg)
+            })?;
+        let (size, sha256) = reader.finish();
+        self.manifest.files.push(FileEntry {
+            path: name.to_owned(),
+            size,
+            sha256,
+        });
+        Ok(())
+    }
+}
+
+/// A header of a regular file, which is created now.
+fn new_header(size: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(size);
+    header.set_mode(0o644);
+    let mtime = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|dur| dur.as_secs())
+        .unwrap_or_default();
+    header.set_mtime(mtime);
+    header
+}
+
+/// Unpacks a tarball into `dst_dir`, the compression is detected from its content.
+pub(crate) fn extract(path: &Path, dst_dir: &Path) -> Result<()> {
+    let mut archive = open(path)?;
+    archive.set_preserve_permissions(true);
+    archive.unpack(dst_dir).map_err(|err| {
+        let msg = format!(
+            "failed to unpack '{}' into '{}' since {}",
+            path.display(),
+            dst_dir.display(),
+            err
+        );
+        Error::Exec(msg)
+    })
+}
+
+/// Checks all files in a tarball against its manifest, without unpacking it.
+///
+/// Returns the manifest, or `None` if the tarball has no manifest, such as an old one.
+pub(crate) fn verify(path: &Path) -> Result<Option<Manifest>> {
+    let mut archive = open(path)?;
+    let entries = archive.entries().map_err(|err| {
+        let msg = format!("failed to read '{}' since {}", path.display(), err);
+        Error::Exec(msg)
+    })?;
+    let mut checksums = HashMap::new();
+    let mut manifest = None;
+    for entry in entries {
+        let read_error = |err: io::Error| {
+            let msg = format!("failed to read '{}' since {}", path.display(), err);
+            Error::Exec(msg)
+        };
+        let mut entry = entry.map_err(read_error)?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+        let name = entry
+            .path()
+            .map_err(read_error)?
+            .to_string_lossy()
+            .into_owned();
+        if name == MANIFEST_FILE_NAME {
+            let data: Manifest = serde_json::from_reader(&mut entry).map_err(|err| {
+                let msg = format!("failed to parse the manifest since {}", err);
+                Error::Exec(msg)
+            })?;
+            manifest = Some(data);
+        } else {
+            let mut reader = HashingReader::new(&mut entry);
+            io::copy(&mut reader, &mut io::sink()).map_err(read_error)?;
+            checksums.insert(name, reader.finish());
+        }
+    }
+    if let Some(manifest) = &manifest {
+        manifest.verify_checksums(&checksums)?;
+    }
+    Ok(manifest)
+}
+
+/// Opens a tarball, the compression is detected from its content.
+fn open(path: &Path) -> Result<Archive<Box<dyn Read>>> {
+    let mut file = File::open(path).map(BufReader::new).map_err(|err| {
+        let msg = format!("failed to open '{}' since {}", path.display(), err);
+        Error::Exec(msg)
+    })?;
+    let mut magic = [0u8; 32];
+    let magic_len = file.read(&mut magic).map_err(|err| {
+        let msg = format!("failed to read '{}' since {}", path.display(), err);
+        Error::Exec(msg)
+    })?;
+    let magic = &magic[..magic_len];
+    let file = io::Cursor::new(magic.to_owned()).chain(file);
+    let decoder: Box<dyn Read> = if magic.starts_with(GZIP_MAGIC) {
+        Box::new(MultiGzDecoder::new(file))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        let decoder = zstd::Decoder::new(file).map_err(|err| {
+            let msg = format!("failed to create zstd decoder since {}", err);
+            Error::Exec(msg)
+        })?;
+        Box::new(decoder)
+    } else if magic.starts_with(XZ_MAGIC) {
+        Box::new(XzDecoder::new(file))
+    } else if magic.starts_with(encryption::MAGIC) {
+        let msg = format!(
+            "'{}' is encrypted, decrypt it by `ckbdev decrypt` first",
+            path.display()
+        );
+        return Err(Error::Exec(msg));
+    } else {
+        let msg = format!(
+            "unknown compression of '{}', it should be gzip, zstd or xz",
+            path.display()
+        );
+        return Err(Error::Exec(msg));
+    };
+    Ok(Archive::new(decoder))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Archives a file, a directory, bytes and a spooled stream, then checks them without and
+    /// with unpacking.
+    fn round_trip(compression: Compression) {
+        let tmp_dir = TempDir::new().unwrap();
+        let src_dir = tmp_dir.path().join("src");
+        fs::create_dir_all(src_dir.join("db/sub")).unwrap();
+        fs::write(src_dir.join("db/000001.sst"), vec![7u8; 100_000]).unwrap();
+        fs::write(src_dir.join("db/sub/CURRENT"), "MANIFEST-000001\n").unwrap();
+        fs::write(src_dir.join("ckb.toml"), "data_dir = \"data\"\n").unwrap();
+
+        let path = tmp_dir
+            .path()
+            .join(format!("test.{}", compression.extension()));
+        let manifest = Manifest {
+            host: Some("testhost".to_owned()),
+            ..Default::default()
+        };
+        let mut writer = ArchiveWriter::create(&path, compression, manifest).unwrap();
+        writer
+            .append_file("ckb.toml", &src_dir.join("ckb.toml"))
+            .unwrap();
+        writer.append_dir("db", &src_dir.join("db")).unwrap();
+        writer.append_bytes("rpc/get_peers.json", b"[]").unwrap();
+        let mut spool = writer.spool().unwrap();
+        for index in 0..1000 {
+            writeln!(spool, "record {}", index).unwrap();
+        }
+        writer.append_spooled("ckb.log", spool).unwrap();
+        writer.finish().unwrap();
+
+        let manifest = verify(&path).unwrap().expect("the manifest");
+        assert_eq!(manifest.host.as_deref(), Some("testhost"));
+        let mut names = manifest
+            .files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "ckb.log",
+                "ckb.toml",
+                "db/000001.sst",
+                "db/sub/CURRENT",
+                "rpc/get_peers.json"
+            ]
+        );
+
+        let dst_dir = tmp_dir.path().join("dst");
+        fs::create_dir(&dst_dir).unwrap();
+        extract(&path, &dst_dir).unwrap();
+        manifest.verify(&dst_dir).unwrap();
+        assert_eq!(
+            fs::read(dst_dir.join("db/000001.sst")).unwrap(),
+            vec![7u8; 100_000]
+        );
+        let log = fs::read_to_string(dst_dir.join("ckb.log")).unwrap();
+        assert_eq!(log.lines().count(), 1000);
+        assert_eq!(log.lines().last(), Some("record 999"));
+    }
+
+    #[test]
+    fn round_trip_gzip() {
+        round_trip(Compression::Gzip(6));
+    }
+
+    #[test]
+    fn round_trip_zstd() {
+        round_trip(Compression::Zstd(3));
+    }
+
+    #[test]
+    fn round_trip_xz() {
+        round_trip(Compression::Xz(6));
+    }
+
+    #[test]
+    fn detect_corrupted_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("test.tar.gz");
+        // A stale entry in the manifest, as if the file was changed after it was hashed.
+        let manifest = Manifest {
+            files: vec![FileEntry {
+                path: "ckb.toml".to_owned(),
+                size: 1,
+                sha256: "00".to_owned(),
+            }],
+            ..Default::default()
+        };
+        let mut writer = ArchiveWriter::create(&path, Compression::default(), manifest).unwrap();
+        writer.append_bytes("other.txt", b"other").unwrap();
+        writer.finish().unwrap();
+        assert!(verify(&path).is_err());
+    }
+
+    #[test]
+    fn reject_unknown_compression() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("test.tar.gz");
+        fs::write(&path, b"not a tarball").unwrap();
+        assert!(verify(&path).is_err());
+    }
+}
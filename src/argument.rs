@@ -1,13 +1,35 @@
-use std::convert::TryFrom;
+use std::{
+    convert::TryFrom,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration as StdDuration,
+};
 
-use chrono::{DateTime, Duration, FixedOffset};
+use chrono::Duration;
+use regex::Regex;
+use url::Url;
 
-use crate::error::{Error, Result};
+use crate::{
+    archive::Compression,
+    ckb_log::RecordFilter,
+    encryption,
+    error::{Error, Result},
+    storage,
+    time_range::{self, TimeRange},
+};
+
+const DEFAULT_WINDOW_MINUTES: i64 = 20;
+/// Tags are in metadata of objects, which are HTTP headers, so they are short and plain.
+const MAX_TAG_LEN: usize = 64;
 
 pub enum Args {
     L1(L1Args),
     L2(L2Args),
     Backup(BackupArgs),
+    Logs(LogsArgs),
+    Restore(RestoreArgs),
+    Decrypt(DecryptArgs),
     Rpc(RpcArgs),
 }
 
@@ -16,21 +38,173 @@ pub enum L1Args {
     Start,
     Restart,
     Status,
-    ResetData { peer_store: bool },
+    ResetData {
+        peer_store: bool,
+        stop_first: bool,
+        yes: bool,
+        backup_dir: Option<PathBuf>,
+        backup_upload: bool,
+    },
+    Upgrade {
+        binary: PathBuf,
+        sha256: Option<String>,
+        no_checksum: bool,
+        timeout: StdDuration,
+        snapshot_dir: Option<PathBuf>,
+        yes: bool,
+    },
+    Migrate {
+        check: bool,
+        snapshot_dir: Option<PathBuf>,
+        yes: bool,
+    },
+    Rollback {
+        timeout: StdDuration,
+    },
 }
 
 pub struct L2Args {}
 
-pub struct BackupArgs {
-    pub(crate) logs_around: (DateTime<FixedOffset>, DateTime<FixedOffset>),
+pub enum BackupArgs {
+    Files(BackupFilesArgs),
+    Snapshot {
+        stop_first: bool,
+        output: ArchiveOutput,
+    },
+    Diag(BackupDiagArgs),
+    List,
+    Check,
+    Fetch {
+        key: String,
+        output_dir: PathBuf,
+        identity: Option<PathBuf>,
+    },
+    Share {
+        key: String,
+        url_ttl: Duration,
+    },
+    Resume {
+        file: PathBuf,
+        url_ttl: Duration,
+    },
+    Prune {
+        keep_days: Option<u32>,
+        keep_last: Option<usize>,
+        dry_run: bool,
+        yes: bool,
+    },
+}
+
+/// How an archive is created and where it goes after.
+pub struct ArchiveOutput {
+    pub(crate) compression: Compression,
+    /// The directory to keep the archive in.
+    pub(crate) dir: Option<PathBuf>,
+    pub(crate) upload: bool,
+    /// How long the download URL is valid.
+    pub(crate) url_ttl: Duration,
+    /// A tag in the metadata of the uploaded archive.
+    pub(crate) tag: Option<String>,
+}
+
+pub struct BackupFilesArgs {
+    pub(crate) time_range: TimeRange,
+    pub(crate) filter: RecordFilter,
     pub(crate) peer_store: bool,
+    pub(crate) with_journal: bool,
+    pub(crate) with_jsonl: bool,
+    /// The directory to save the redaction mapping into, if redact.
+    pub(crate) redact: Option<PathBuf>,
+    pub(crate) output: ArchiveOutput,
+}
+
+pub struct BackupDiagArgs {
+    pub(crate) time_range: TimeRange,
+    pub(crate) filter: RecordFilter,
+    /// The directory to save the redaction mapping into, if redact.
+    pub(crate) redact: Option<PathBuf>,
+    pub(crate) output: ArchiveOutput,
+}
+
+pub enum LogsArgs {
+    Show {
+        time_range: TimeRange,
+        filter: RecordFilter,
+    },
+    Tail {
+        lines: usize,
+        follow: bool,
+        color: ColorMode,
+        filter: RecordFilter,
+    },
+    Export {
+        time_range: TimeRange,
+        format: ExportFormat,
+        output: Option<PathBuf>,
+        filter: RecordFilter,
+    },
+    Summarize {
+        time_range: TimeRange,
+        top: usize,
+    },
+    Journal {
+        time_range: TimeRange,
+    },
+}
+
+pub enum ExportFormat {
+    JsonLines,
+}
+
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+pub enum RestoreArgs {
+    Snapshot {
+        archive: ArchiveSource,
+        stop_first: bool,
+        yes: bool,
+    },
+    PeerStore {
+        archive: ArchiveSource,
+        stop_first: bool,
+        yes: bool,
+    },
+}
+
+pub enum ArchiveSource {
+    Local(PathBuf),
+    Remote(Url),
+}
+
+pub struct DecryptArgs {
+    pub(crate) archive: PathBuf,
+    pub(crate) identity: Option<PathBuf>,
+    pub(crate) passphrase_file: Option<PathBuf>,
+    pub(crate) output: PathBuf,
 }
 
 pub enum RpcArgs {
     GetPeers { stats: bool },
 }
 
+impl ArchiveOutput {
+    /// The path to create the archive at, it is in `tmp_dir` unless it should be kept.
+    pub(crate) fn archive_path(&self, tmp_dir: &Path, name: &str) -> PathBuf {
+        self.dir.as_deref().unwrap_or(tmp_dir).join(format!(
+            "{}.{}",
+            name,
+            self.compression.extension()
+        ))
+    }
+}
+
 impl Args {
+    // `crate_authors!` of clap 2 trips the lint of newer compilers.
+    #[allow(unknown_lints, dangerous_implicit_autorefs)]
     pub fn load_from_inputs() -> Result<Self> {
         let yaml = clap::load_yaml!("argument.yaml");
         let matches = clap::App::from_yaml(yaml)
@@ -39,6 +213,11 @@ impl Args {
             .get_matches();
         Self::try_from(&matches)
     }
+
+    /// Decrypting is usually done on another machine, which does not have the config.
+    pub fn requires_config(&self) -> bool {
+        !matches!(self, Self::Decrypt(_))
+    }
 }
 
 impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for Args {
@@ -48,6 +227,9 @@ impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for Args {
             ("l1", Some(matches)) => L1Args::try_from(matches).map(Self::L1),
             ("l2", Some(matches)) => L2Args::try_from(matches).map(Self::L2),
             ("backup", Some(matches)) => BackupArgs::try_from(matches).map(Self::Backup),
+            ("logs", Some(matches)) => LogsArgs::try_from(matches).map(Self::Logs),
+            ("restore", Some(matches)) => RestoreArgs::try_from(matches).map(Self::Restore),
+            ("decrypt", Some(matches)) => DecryptArgs::try_from(matches).map(Self::Decrypt),
             ("rpc", Some(matches)) => RpcArgs::try_from(matches).map(Self::Rpc),
             _ => unreachable!(),
         }
@@ -64,7 +246,50 @@ impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for L1Args {
             ("status", Some(_matches)) => Ok(Self::Status),
             ("reset-data", Some(matches)) => {
                 let peer_store = matches.is_present("peer-store");
-                Ok(Self::ResetData { peer_store })
+                let stop_first = matches.is_present("stop-first");
+                let yes = matches.is_present("yes");
+                let backup_dir = matches.value_of("backup-dir").map(PathBuf::from);
+                let backup_upload = matches.is_present("backup-upload");
+                Ok(Self::ResetData {
+                    peer_store,
+                    stop_first,
+                    yes,
+                    backup_dir,
+                    backup_upload,
+                })
+            }
+            ("upgrade", Some(matches)) => {
+                let binary = matches
+                    .value_of("binary")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| unreachable!());
+                let sha256 = matches.value_of("sha256").map(str::to_lowercase);
+                let no_checksum = matches.is_present("no-checksum");
+                let timeout = parse_seconds(matches, "timeout")?;
+                let snapshot_dir = matches.value_of("snapshot-dir").map(PathBuf::from);
+                let yes = matches.is_present("yes");
+                Ok(Self::Upgrade {
+                    binary,
+                    sha256,
+                    no_checksum,
+                    timeout,
+                    snapshot_dir,
+                    yes,
+                })
+            }
+            ("migrate", Some(matches)) => {
+                let check = matches.is_present("check");
+                let snapshot_dir = matches.value_of("snapshot-dir").map(PathBuf::from);
+                let yes = matches.is_present("yes");
+                Ok(Self::Migrate {
+                    check,
+                    snapshot_dir,
+                    yes,
+                })
+            }
+            ("rollback", Some(matches)) => {
+                let timeout = parse_seconds(matches, "timeout")?;
+                Ok(Self::Rollback { timeout })
             }
             _ => unreachable!(),
         }
@@ -81,23 +306,236 @@ impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for L2Args {
 impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for BackupArgs {
     type Error = Error;
     fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
-        let logs_around = matches
-            .value_of("logs-around")
-            .map(|s| {
-                DateTime::parse_from_rfc3339(s)
-                    .map_err(|err| {
-                        Error::Arg(format!("failed to parse \"logs-around\" since {}", err))
-                    })
-                    .map(|base| {
-                        let dur = Duration::minutes(10);
-                        (base - dur, base + dur)
-                    })
-            })
-            .unwrap_or_else(|| unreachable!())?;
+        match matches.subcommand() {
+            ("snapshot", Some(matches)) => {
+                let stop_first = matches.is_present("stop-first");
+                let output = parse_archive_output(matches)?;
+                Ok(Self::Snapshot { stop_first, output })
+            }
+            ("diag", Some(matches)) => BackupDiagArgs::try_from(matches).map(Self::Diag),
+            ("list", Some(_matches)) => Ok(Self::List),
+            ("check", Some(_matches)) => Ok(Self::Check),
+            ("fetch", Some(matches)) => {
+                let key = matches
+                    .value_of("key")
+                    .map(ToOwned::to_owned)
+                    .unwrap_or_else(|| unreachable!());
+                let output_dir = matches
+                    .value_of("output")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| unreachable!());
+                let identity = matches.value_of("identity").map(PathBuf::from);
+                Ok(Self::Fetch {
+                    key,
+                    output_dir,
+                    identity,
+                })
+            }
+            ("share", Some(matches)) => {
+                let key = matches
+                    .value_of("key")
+                    .map(ToOwned::to_owned)
+                    .unwrap_or_else(|| unreachable!());
+                let url_ttl = parse_url_ttl(matches)?;
+                Ok(Self::Share { key, url_ttl })
+            }
+            ("resume", Some(matches)) => {
+                let file = matches
+                    .value_of("file")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| unreachable!());
+                let url_ttl = parse_url_ttl(matches)?;
+                Ok(Self::Resume { file, url_ttl })
+            }
+            ("prune", Some(matches)) => {
+                let keep_days = parse_optional(matches, "keep-days")?;
+                let keep_last = parse_optional(matches, "keep-last")?;
+                if keep_last == Some(0) {
+                    let msg = "failed to parse \"keep-last\" since it should be at least 1";
+                    return Err(Error::Arg(msg.to_owned()));
+                }
+                let dry_run = matches.is_present("dry-run");
+                let yes = matches.is_present("yes");
+                Ok(Self::Prune {
+                    keep_days,
+                    keep_last,
+                    dry_run,
+                    yes,
+                })
+            }
+            _ => BackupFilesArgs::try_from(matches).map(Self::Files),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for BackupFilesArgs {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        let time_range = parse_time_range(matches)?;
+        let filter = parse_record_filter(matches)?;
         let peer_store = matches.is_present("peer-store");
+        let with_journal = matches.is_present("with-journal");
+        let with_jsonl = matches.is_present("with-jsonl");
+        let redact = parse_redact(matches)?;
+        let output = parse_archive_output(matches)?;
         Ok(Self {
-            logs_around,
+            time_range,
+            filter,
             peer_store,
+            with_journal,
+            with_jsonl,
+            redact,
+            output,
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for BackupDiagArgs {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        let time_range = parse_time_range(matches)?;
+        let filter = parse_record_filter(matches)?;
+        let redact = parse_redact(matches)?;
+        let output = parse_archive_output(matches)?;
+        Ok(Self {
+            time_range,
+            filter,
+            redact,
+            output,
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for LogsArgs {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        match matches.subcommand() {
+            ("show", Some(matches)) => {
+                let time_range = parse_time_range(matches)?;
+                let filter = parse_record_filter(matches)?;
+                Ok(Self::Show { time_range, filter })
+            }
+            ("tail", Some(matches)) => {
+                let lines = parse_count(matches, "lines")?;
+                let follow = matches.is_present("follow");
+                let color = match matches.value_of("color") {
+                    Some("always") => ColorMode::Always,
+                    Some("never") => ColorMode::Never,
+                    _ => ColorMode::Auto,
+                };
+                let filter = parse_record_filter(matches)?;
+                Ok(Self::Tail {
+                    lines,
+                    follow,
+                    color,
+                    filter,
+                })
+            }
+            ("export", Some(matches)) => {
+                let time_range = parse_time_range(matches)?;
+                let format = match matches.value_of("format") {
+                    Some("jsonl") => ExportFormat::JsonLines,
+                    _ => unreachable!(),
+                };
+                let output = matches.value_of("output").map(PathBuf::from);
+                let filter = parse_record_filter(matches)?;
+                Ok(Self::Export {
+                    time_range,
+                    format,
+                    output,
+                    filter,
+                })
+            }
+            ("summarize", Some(matches)) => {
+                let time_range = parse_time_range(matches)?;
+                let top = parse_count(matches, "top")?;
+                Ok(Self::Summarize { time_range, top })
+            }
+            ("journal", Some(matches)) => {
+                let time_range = parse_time_range(matches)?;
+                Ok(Self::Journal { time_range })
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for RestoreArgs {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        match matches.subcommand() {
+            ("snapshot", Some(matches)) => {
+                let archive = matches
+                    .value_of("archive")
+                    .map(ArchiveSource::from)
+                    .unwrap_or_else(|| unreachable!());
+                let stop_first = matches.is_present("stop-first");
+                let yes = matches.is_present("yes");
+                Ok(Self::Snapshot {
+                    archive,
+                    stop_first,
+                    yes,
+                })
+            }
+            ("peer-store", Some(matches)) => {
+                let archive = matches
+                    .value_of("archive")
+                    .map(ArchiveSource::from)
+                    .unwrap_or_else(|| unreachable!());
+                let stop_first = matches.is_present("stop-first");
+                let yes = matches.is_present("yes");
+                Ok(Self::PeerStore {
+                    archive,
+                    stop_first,
+                    yes,
+                })
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for ArchiveSource {
+    fn from(s: &'a str) -> Self {
+        match Url::parse(s) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Self::Remote(url),
+            _ => Self::Local(PathBuf::from(s)),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for DecryptArgs {
+    type Error = Error;
+    fn try_from(matches: &'a clap::ArgMatches) -> Result<Self> {
+        let archive = matches
+            .value_of("archive")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| unreachable!());
+        let identity = matches.value_of("identity").map(PathBuf::from);
+        let passphrase_file = matches.value_of("passphrase-file").map(PathBuf::from);
+        let output = match matches.value_of("output") {
+            Some(output) => PathBuf::from(output),
+            None => {
+                let extension = format!(".{}", encryption::EXTENSION);
+                archive
+                    .to_str()
+                    .and_then(|path| path.strip_suffix(&extension))
+                    .map(PathBuf::from)
+                    .ok_or_else(|| {
+                        let msg = format!(
+                            "the archive '{}' does not end with \"{}\", the output is required",
+                            archive.display(),
+                            extension
+                        );
+                        Error::Arg(msg)
+                    })?
+            }
+        };
+        Ok(Self {
+            archive,
+            identity,
+            passphrase_file,
+            output,
         })
     }
 }
@@ -114,3 +552,176 @@ impl<'a> TryFrom<&'a clap::ArgMatches<'a>> for RpcArgs {
         }
     }
 }
+
+fn parse_time_range(matches: &clap::ArgMatches) -> Result<TimeRange> {
+    let now = time_range::now();
+    let parse = |name: &str| {
+        matches
+            .value_of(name)
+            .map(|s| {
+                time_range::parse_datetime(s, now).map_err(|err| {
+                    Error::Arg(format!("failed to parse \"{}\" since {}", name, err))
+                })
+            })
+            .transpose()
+    };
+    let window = matches
+        .value_of("window")
+        .map(|s| {
+            time_range::parse_duration(s)
+                .map_err(|err| Error::Arg(format!("failed to parse \"window\" since {}", err)))
+        })
+        .transpose()?;
+    if let Some(around) = parse("around")? {
+        let window = window.unwrap_or_else(|| Duration::minutes(DEFAULT_WINDOW_MINUTES));
+        TimeRange::around(around, window).map_err(Error::Arg)
+    } else if let Some(from) = parse("from")?.or(parse("since")?) {
+        let to = match (parse("to")?, window) {
+            (Some(to), _) => to,
+            (None, Some(window)) => time_range::checked_add(from, window).map_err(Error::Arg)?,
+            (None, None) => now,
+        };
+        TimeRange::new(from, to).map_err(Error::Arg)
+    } else {
+        unreachable!()
+    }
+}
+
+fn parse_record_filter(matches: &clap::ArgMatches) -> Result<RecordFilter> {
+    let parse_regex = |name: &str| {
+        matches
+            .value_of(name)
+            .map(|s| {
+                Regex::new(s).map_err(|err| {
+                    Error::Arg(format!("failed to parse \"{}\" since {}", name, err))
+                })
+            })
+            .transpose()
+    };
+    let level = matches
+        .value_of("level")
+        .map(|s| s.parse().expect("level should be checked"));
+    let modules = matches
+        .values_of("module")
+        .map(|values| values.map(ToOwned::to_owned).collect())
+        .unwrap_or_default();
+    let grep = parse_regex("grep")?;
+    let exclude = parse_regex("exclude")?;
+    Ok(RecordFilter {
+        level,
+        modules,
+        grep,
+        exclude,
+    })
+}
+
+/// Returns the directory to save the redaction mapping into, if redact.
+///
+/// The mapping reveals the masked values, so it is never saved into the current directory by
+/// default, which could be a repository or a shared directory.
+fn parse_redact(matches: &clap::ArgMatches) -> Result<Option<PathBuf>> {
+    if !matches.is_present("redact") {
+        return Ok(None);
+    }
+    matches
+        .value_of("redact-mapping-dir")
+        .or_else(|| matches.value_of("output"))
+        .map(|dir| Some(PathBuf::from(dir)))
+        .ok_or_else(|| {
+            let msg = "\"redact\" requires \"redact-mapping-dir\" or \"output\" to save the \
+                       redaction mapping into";
+            Error::Arg(msg.to_owned())
+        })
+}
+
+fn parse_archive_output(matches: &clap::ArgMatches) -> Result<ArchiveOutput> {
+    let level = matches
+        .value_of("compression-level")
+        .map(|s| {
+            s.parse().map_err(|err| {
+                Error::Arg(format!(
+                    "failed to parse \"compression-level\" since {}",
+                    err
+                ))
+            })
+        })
+        .transpose()?;
+    let compression = matches
+        .value_of("compression")
+        .map(|format| Compression::new(format, level).map_err(Error::Arg))
+        .unwrap_or_else(|| unreachable!())?;
+    let dir = matches.value_of("output").map(PathBuf::from);
+    let upload = !matches.is_present("no-upload");
+    let url_ttl = parse_url_ttl(matches)?;
+    let tag = matches
+        .value_of("tag")
+        .map(|tag| {
+            let is_valid = !tag.is_empty()
+                && tag.len() <= MAX_TAG_LEN
+                && tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+            if is_valid {
+                Ok(tag.to_owned())
+            } else {
+                let msg = format!(
+                    "invalid tag \"{}\", it should be at most {} letters, digits, \"-\", \"_\" or \".\"",
+                    tag, MAX_TAG_LEN
+                );
+                Err(Error::Arg(msg))
+            }
+        })
+        .transpose()?;
+    Ok(ArchiveOutput {
+        compression,
+        dir,
+        upload,
+        url_ttl,
+        tag,
+    })
+}
+
+fn parse_url_ttl(matches: &clap::ArgMatches) -> Result<Duration> {
+    matches
+        .value_of("url-ttl")
+        .map(|s| {
+            time_range::parse_duration(s)
+                .map_err(|err| Error::Arg(format!("failed to parse \"url-ttl\" since {}", err)))
+        })
+        .unwrap_or_else(|| Ok(Duration::days(storage::DEFAULT_URL_TTL_DAYS)))
+}
+
+fn parse_optional<T>(matches: &clap::ArgMatches, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    matches
+        .value_of(name)
+        .map(|s| {
+            s.parse()
+                .map_err(|err| Error::Arg(format!("failed to parse \"{}\" since {}", name, err)))
+        })
+        .transpose()
+}
+
+fn parse_count(matches: &clap::ArgMatches, name: &str) -> Result<usize> {
+    matches
+        .value_of(name)
+        .map(|s| {
+            s.parse()
+                .map_err(|err| Error::Arg(format!("failed to parse \"{}\" since {}", name, err)))
+        })
+        .unwrap_or_else(|| unreachable!())
+}
+
+fn parse_seconds(matches: &clap::ArgMatches, name: &str) -> Result<StdDuration> {
+    matches
+        .value_of(name)
+        .map(|s| {
+            s.parse::<u64>()
+                .map(StdDuration::from_secs)
+                .map_err(|err| Error::Arg(format!("failed to parse \"{}\" since {}", name, err)))
+        })
+        .unwrap_or_else(|| unreachable!())
+}
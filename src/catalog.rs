@@ -0,0 +1,481 @@
+use std::{collections::HashMap, path::Path};
+
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone as _, Utc};
+use regex::Regex;
+
+use crate::{
+    encryption,
+    error::{Error, Result},
+    storage::{self, Storage},
+};
+
+/// Kinds in names of archives, such as "{host}-snapshot-{timestamp}.tar.gz".
+///
+/// Archives of files have no kind in names.
+const KINDS: &[&str] = &["snapshot", "diag", "reset-data", "db"];
+const FILES_KIND: &str = "files";
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+const TIMESTAMP_REGEX: &str = r"\d{8}-\d{6}";
+const EXT_REGEX: &str = r"tar\.(?:gz|zst|xz)(?:\.age)?";
+/// Keys are the prefix of the storage followed by file names by default.
+pub(crate) const DEFAULT_KEY_TEMPLATE: &str = "{prefix}{name}";
+
+/// A template of keys of uploaded archives, such as
+/// "{prefix}{host}/{yyyy}/{mm}/{kind}-{timestamp}.{ext}".
+///
+/// Placeholders are:
+/// - "{prefix}": the prefix of keys of the storage.
+/// - "{host}": the host name.
+/// - "{kind}": "snapshot", "diag", "reset-data", "db" or "files".
+/// - "{timestamp}": when the archive is created, such as "20210820-100000".
+/// - "{yyyy}", "{mm}" and "{dd}": the year, the month and the day of the timestamp.
+/// - "{ext}": the extension, such as "tar.gz" or "tar.zst.age".
+/// - "{name}": the file name, which is "{host}-{kind}-{timestamp}.{ext}", but archives of files
+///   have no kind.
+pub(crate) struct KeyTemplate {
+    segments: Vec<Segment>,
+}
+
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Prefix,
+    Host,
+    Kind,
+    Timestamp,
+    Year,
+    Month,
+    Day,
+    Ext,
+    Name,
+}
+
+/// An uploaded archive of this host.
+pub(crate) struct Archive {
+    pub(crate) key: String,
+    pub(crate) kind: String,
+    /// When the archive was created, it is in the name.
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) size: u64,
+}
+
+impl KeyTemplate {
+    pub(crate) fn parse(template: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            let msg = format!(
+                "invalid [storage.key_template] \"{}\", {}",
+                template, reason
+            );
+            Error::Cfg(msg)
+        };
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            let start = rest.find('{').unwrap_or(rest.len());
+            if start > 0 {
+                if rest[..start].contains('}') {
+                    return Err(invalid("\"}\" is not paired"));
+                }
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+                rest = &rest[start..];
+                continue;
+            }
+            let end = rest
+                .find('}')
+                .ok_or_else(|| invalid("\"{\" is not paired"))?;
+            let placeholder = match &rest[1..end] {
+                "prefix" => Placeholder::Prefix,
+                "host" => Placeholder::Host,
+                "kind" => Placeholder::Kind,
+                "timestamp" => Placeholder::Timestamp,
+                "yyyy" => Placeholder::Year,
+                "mm" => Placeholder::Month,
+                "dd" => Placeholder::Day,
+                "ext" => Placeholder::Ext,
+                "name" => Placeholder::Name,
+                name => return Err(invalid(&format!("unknown placeholder \"{{{}}}\"", name))),
+            };
+            segments.push(Segment::Placeholder(placeholder));
+            rest = &rest[end + 1..];
+        }
+        let count = |placeholder| {
+            segments
+                .iter()
+                .filter(|segment| matches!(segment, Segment::Placeholder(p) if *p == placeholder))
+                .count()
+        };
+        // Keys of archives should be unique among hosts, and they should be parsed back by the
+        // catalog, so only archives of this host are listed and pruned.
+        let parts = [
+            Placeholder::Host,
+            Placeholder::Kind,
+            Placeholder::Timestamp,
+            Placeholder::Ext,
+        ];
+        let is_valid = match count(Placeholder::Name) {
+            0 => parts.iter().all(|placeholder| count(*placeholder) == 1),
+            1 => parts[1..]
+                .iter()
+                .all(|placeholder| count(*placeholder) == 0),
+            _ => false,
+        };
+        if !is_valid {
+            return Err(invalid(
+                "it should have \"{name}\", or \"{host}\", \"{kind}\", \"{timestamp}\" and \
+                \"{ext}\" instead, and each of them only once",
+            ));
+        }
+        Ok(Self { segments })
+    }
+
+    /// The key of the archive file of the host.
+    pub(crate) fn render(&self, prefix: &str, host: &str, file_path: &Path) -> Result<String> {
+        let name = storage::object_name(file_path)?;
+        let regex = Regex::new(&format!("^{}$", name_pattern(host))).expect("compile regex");
+        let caps = regex.captures(name).ok_or_else(|| {
+            let msg = format!("'{}' is not an archive of this host", file_path.display());
+            Error::Storage(msg)
+        })?;
+        let created =
+            NaiveDateTime::parse_from_str(&caps["timestamp"], TIMESTAMP_FORMAT).map_err(|err| {
+                let msg = format!("failed to parse the time of '{}' since {}", name, err);
+                Error::Storage(msg)
+            })?;
+        let key = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.to_owned(),
+                Segment::Placeholder(placeholder) => match placeholder {
+                    Placeholder::Prefix => prefix.to_owned(),
+                    Placeholder::Host => host.to_owned(),
+                    Placeholder::Kind => caps
+                        .name("kind")
+                        .map_or(FILES_KIND, |kind| kind.as_str())
+                        .to_owned(),
+                    Placeholder::Timestamp => caps["timestamp"].to_owned(),
+                    Placeholder::Year => created.format("%Y").to_string(),
+                    Placeholder::Month => created.format("%m").to_string(),
+                    Placeholder::Day => created.format("%d").to_string(),
+                    Placeholder::Ext => caps["ext"].to_owned(),
+                    Placeholder::Name => name.to_owned(),
+                },
+            })
+            .collect();
+        Ok(key)
+    }
+
+    /// The regex which matches keys of archives of the host, it captures the kind, the
+    /// timestamp and the extension.
+    fn regex(&self, prefix: &str, host: &str) -> Regex {
+        let kinds = KINDS
+            .iter()
+            .chain(&[FILES_KIND])
+            .copied()
+            .collect::<Vec<_>>()
+            .join("|");
+        let pattern = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => regex::escape(literal),
+                Segment::Placeholder(placeholder) => match placeholder {
+                    Placeholder::Prefix => regex::escape(prefix),
+                    Placeholder::Host => regex::escape(host),
+                    Placeholder::Kind => format!("(?P<kind>{})", kinds),
+                    Placeholder::Timestamp => format!("(?P<timestamp>{})", TIMESTAMP_REGEX),
+                    Placeholder::Year => r"\d{4}".to_owned(),
+                    Placeholder::Month | Placeholder::Day => r"\d{2}".to_owned(),
+                    Placeholder::Ext => format!("(?P<ext>{})", EXT_REGEX),
+                    Placeholder::Name => name_pattern(host),
+                },
+            })
+            .collect::<String>();
+        Regex::new(&format!("^{}$", pattern)).expect("compile regex")
+    }
+
+    /// The longest prefix which keys of archives of the host start with, to list them.
+    fn list_prefix(&self, prefix: &str, host: &str) -> String {
+        let mut list_prefix = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => list_prefix.push_str(literal),
+                Segment::Placeholder(Placeholder::Prefix) => list_prefix.push_str(prefix),
+                Segment::Placeholder(Placeholder::Host) => list_prefix.push_str(host),
+                Segment::Placeholder(Placeholder::Name) => {
+                    list_prefix.push_str(host);
+                    list_prefix.push('-');
+                    break;
+                }
+                Segment::Placeholder(_) => break,
+            }
+        }
+        list_prefix
+    }
+}
+
+/// The MIME type of an archive file by its extension.
+pub(crate) fn content_type(file_path: &Path) -> &'static str {
+    let name = file_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    if name.ends_with(&format!(".{}", encryption::EXTENSION)) {
+        "application/octet-stream"
+    } else if name.ends_with(".tar.gz") {
+        "application/gzip"
+    } else if name.ends_with(".tar.zst") {
+        "application/zstd"
+    } else if name.ends_with(".tar.xz") {
+        "application/x-xz"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// The pattern of file names of archives of the host, which is not anchored.
+fn name_pattern(host: &str) -> String {
+    format!(
+        r"{}-(?:(?P<kind>{})-)?(?P<timestamp>{})\.(?P<ext>{})",
+        regex::escape(host),
+        KINDS.join("|"),
+        TIMESTAMP_REGEX,
+        EXT_REGEX
+    )
+}
+
+/// Lists archives of the host in the storage, from the oldest to the newest.
+///
+/// Other objects are ignored, such as archives of other hosts which names start with this host.
+pub(crate) fn list(
+    storage: &dyn Storage,
+    template: &KeyTemplate,
+    host: &str,
+) -> Result<Vec<Archive>> {
+    let prefix = storage.key_prefix();
+    let regex = template.regex(prefix, host);
+    let mut archives = storage
+        .list(&template.list_prefix(prefix, host))?
+        .into_iter()
+        .filter_map(|object| {
+            let caps = regex.captures(&object.key)?;
+            let kind = caps
+                .name("kind")
+                .map_or(FILES_KIND, |kind| kind.as_str())
+                .to_owned();
+            let created =
+                NaiveDateTime::parse_from_str(&caps["timestamp"], TIMESTAMP_FORMAT).ok()?;
+            Some(Archive {
+                kind,
+                created: Utc.from_utc_datetime(&created),
+                size: object.size,
+                key: object.key,
+            })
+        })
+        .collect::<Vec<_>>();
+    archives.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.key.cmp(&b.key)));
+    Ok(archives)
+}
+
+/// Archives which should be deleted, the newest `keep_last` archives of each kind are kept, and
+/// so are archives in the last `keep_days` days.
+pub(crate) fn expired(
+    archives: &[Archive],
+    keep_days: Option<u32>,
+    keep_last: Option<usize>,
+    now: DateTime<Utc>,
+) -> Vec<&Archive> {
+    // All archives are kept if the deadline is before the earliest representable time.
+    let deadline = keep_days.map(|days| now.checked_sub_signed(Duration::days(i64::from(days))));
+    let mut newer_counts = HashMap::<&str, usize>::new();
+    let mut expired = archives
+        .iter()
+        .rev()
+        .filter(|archive| {
+            let newer_count = newer_counts.entry(archive.kind.as_str()).or_default();
+            let is_kept_by_count = matches!(keep_last, Some(keep_last) if *newer_count < keep_last);
+            *newer_count += 1;
+            let is_kept_by_days = match deadline {
+                Some(Some(deadline)) => archive.created >= deadline,
+                Some(None) => true,
+                None => false,
+            };
+            !is_kept_by_count && !is_kept_by_days
+        })
+        .collect::<Vec<_>>();
+    expired.reverse();
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        config::LocalSection,
+        storage::{LocalStorage, Metadata},
+    };
+
+    const HOST: &str = "testhost";
+
+    fn archive(kind: &str, created: &str) -> Archive {
+        let created = NaiveDateTime::parse_from_str(created, TIMESTAMP_FORMAT).unwrap();
+        Archive {
+            key: format!("{}-{}", kind, created),
+            kind: kind.to_owned(),
+            created: Utc.from_utc_datetime(&created),
+            size: 0,
+        }
+    }
+
+    fn keys<'a>(archives: impl IntoIterator<Item = &'a Archive>) -> Vec<&'a str> {
+        archives
+            .into_iter()
+            .map(|archive| archive.key.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn render_and_list() {
+        let tmp_dir = TempDir::new().unwrap();
+        let cfg = LocalSection {
+            dir: tmp_dir.path().join("storage"),
+        };
+        let storage = LocalStorage::new(&cfg);
+        let template = KeyTemplate::parse("{host}/{yyyy}/{mm}/{kind}-{timestamp}.{ext}").unwrap();
+        let metadata = Metadata {
+            content_type: "application/gzip".to_owned(),
+            fields: Default::default(),
+        };
+        let names = [
+            "testhost-snapshot-20210820-100000.tar.gz",
+            "testhost-20210819-100000.tar.zst.age",
+            "testhost-diag-20210821-100000.tar.xz",
+        ];
+        for name in &names {
+            let file_path = tmp_dir.path().join(name);
+            fs::write(&file_path, name).unwrap();
+            let key = template.render("", HOST, &file_path).unwrap();
+            storage.put(&file_path, &key, &metadata).unwrap();
+        }
+        // Objects of other hosts and other files are ignored.
+        let other_path = tmp_dir.path().join("other");
+        fs::write(&other_path, "other").unwrap();
+        for key in &[
+            "testhost-2/2021/08/snapshot-20210820-100000.tar.gz",
+            "testhost/2021/08/snapshot-20210820-100000.tar.bz2",
+            "testhost/2021/08/notes.txt",
+        ] {
+            storage.put(&other_path, key, &metadata).unwrap();
+        }
+
+        let archives = list(&storage, &template, HOST).unwrap();
+        assert_eq!(
+            keys(&archives),
+            [
+                "testhost/2021/08/files-20210819-100000.tar.zst.age",
+                "testhost/2021/08/snapshot-20210820-100000.tar.gz",
+                "testhost/2021/08/diag-20210821-100000.tar.xz",
+            ]
+        );
+        let kinds = archives
+            .iter()
+            .map(|archive| archive.kind.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, ["files", "snapshot", "diag"]);
+        assert_eq!(archives[1].size, names[0].len() as u64);
+    }
+
+    #[test]
+    fn list_by_the_default_template() {
+        let template = KeyTemplate::parse(DEFAULT_KEY_TEMPLATE).unwrap();
+        assert_eq!(template.list_prefix("backup/", HOST), "backup/testhost-");
+        let regex = template.regex("backup/", HOST);
+        assert!(regex.is_match("backup/testhost-db-20210820-100000.tar.gz"));
+        assert!(regex.is_match("backup/testhost-20210820-100000.tar.gz"));
+        assert!(!regex.is_match("backup/testhost-2-db-20210820-100000.tar.gz"));
+        assert!(!regex.is_match("testhost-db-20210820-100000.tar.gz"));
+    }
+
+    #[test]
+    fn reject_ambiguous_templates() {
+        for template in &[
+            "{prefix}{kind}-{timestamp}.{ext}",
+            "{prefix}{host}-{timestamp}.{ext}",
+            "{prefix}{host}/{host}-{kind}-{timestamp}.{ext}",
+            "{prefix}{host}/{name}.{ext}",
+            "{prefix}{name}/{name}",
+            "{prefix}{unknown}/{name}",
+            "{prefix}{name",
+            "{prefix}}{name}",
+        ] {
+            assert!(KeyTemplate::parse(template).is_err(), "{}", template);
+        }
+        for template in &[
+            "{prefix}{name}",
+            "{host}/{yyyy}/{name}",
+            "{ext}/{kind}/{host}{timestamp}",
+        ] {
+            assert!(KeyTemplate::parse(template).is_ok(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn render_archives_of_this_host_only() {
+        let template = KeyTemplate::parse(DEFAULT_KEY_TEMPLATE).unwrap();
+        let path = Path::new("/tmp/otherhost-snapshot-20210820-100000.tar.gz");
+        assert!(template.render("", HOST, path).is_err());
+    }
+
+    #[test]
+    fn expire_by_keep_last_of_each_kind() {
+        let archives = [
+            archive("snapshot", "20210801-100000"),
+            archive("diag", "20210802-100000"),
+            archive("snapshot", "20210803-100000"),
+            archive("snapshot", "20210804-100000"),
+            archive("diag", "20210805-100000"),
+        ];
+        let now = Utc.ymd(2021, 8, 20).and_hms(10, 0, 0);
+        let expired = expired(&archives, None, Some(2), now);
+        assert_eq!(keys(expired), ["snapshot-2021-08-01 10:00:00"]);
+    }
+
+    #[test]
+    fn expire_by_keep_days() {
+        let archives = [
+            archive("snapshot", "20210801-100000"),
+            archive("snapshot", "20210810-095959"),
+            archive("snapshot", "20210810-100000"),
+            archive("diag", "20210815-100000"),
+        ];
+        let now = Utc.ymd(2021, 8, 20).and_hms(10, 0, 0);
+        assert_eq!(
+            keys(expired(&archives, Some(10), None, now)),
+            [
+                "snapshot-2021-08-01 10:00:00",
+                "snapshot-2021-08-10 09:59:59"
+            ]
+        );
+        // Archives are kept by either of them.
+        assert_eq!(
+            keys(expired(&archives, Some(1), Some(2), now)),
+            ["snapshot-2021-08-01 10:00:00"]
+        );
+        assert_eq!(
+            keys(expired(&archives, Some(0), Some(1), now)),
+            [
+                "snapshot-2021-08-01 10:00:00",
+                "snapshot-2021-08-10 09:59:59"
+            ]
+        );
+        assert!(expired(&archives, Some(u32::MAX), None, now).is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+use std::{path::Path, process::Command};
+
+use crate::{
+    command,
+    error::{Error, Result},
+};
+
+/// Exit code of `ckb migrate --check` when no migration is required.
+const MIGRATE_NOT_REQUIRED_EXIT_CODE: i32 = 64;
+
+/// The `ckb` executable and the root directory it works in.
+pub(crate) struct CkbCli<'a> {
+    bin_path: &'a Path,
+    root_dir: &'a Path,
+}
+
+#[allow(unknown_lints, clippy::needless_borrows_for_generic_args)]
+impl<'a> CkbCli<'a> {
+    pub(crate) fn new(bin_path: &'a Path, root_dir: &'a Path) -> Self {
+        Self { bin_path, root_dir }
+    }
+
+    pub(crate) fn version(&self) -> Result<String> {
+        command::output(Command::new(self.bin_path).arg("--version"))
+            .map(|output| output.trim().to_owned())
+    }
+
+    pub(crate) fn reset_data(&self, peer_store: bool) -> Result<()> {
+        let mut command = Command::new(self.bin_path);
+        command
+            .args(&["reset-data", "--force", "-C"])
+            .arg(self.root_dir);
+        if peer_store {
+            command.arg("--network-peer-store");
+        } else {
+            command.arg("--all");
+        }
+        command::run(&mut command)
+    }
+
+    /// Checks whether the database requires a migration.
+    pub(crate) fn migrate_check(&self) -> Result<bool> {
+        let mut command = Command::new(self.bin_path);
+        command
+            .args(&["migrate", "--check", "-C"])
+            .arg(self.root_dir);
+        let status = command.status().map_err(|err| {
+            let msg = format!("failed to execute `{:?}` since {}", command, err);
+            Error::Exec(msg)
+        })?;
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(MIGRATE_NOT_REQUIRED_EXIT_CODE) => Ok(false),
+            _ => {
+                let msg = format!("failed to execute `{:?}` since {}", command, status);
+                Err(Error::Exec(msg))
+            }
+        }
+    }
+
+    pub(crate) fn migrate(&self) -> Result<()> {
+        let mut command = Command::new(self.bin_path);
+        command
+            .args(&["migrate", "--force", "-C"])
+            .arg(self.root_dir);
+        command::run(&mut command)
+    }
+}
@@ -0,0 +1,863 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, SecondsFormat, TimeZone as _};
+use flate2::read::MultiGzDecoder;
+use log::Level;
+use regex::Regex;
+use serde_json::{json, Value};
+use walkdir::WalkDir;
+
+use crate::time_range::TimeRange;
+
+/// The head line of a record, which is "{datetime} {thread} {level} {target}  {message}".
+///
+/// Old versions of CKB did not print the thread or the timezone, and some of them used "T" or
+/// "," in the datetime.
+const LOG_HEAD_REGEX: &str = concat!(
+    r"^(?P<datetime>\d{4}-\d{2}-\d{2}[ T]\d{2}:\d{2}:\d{2}(?:[.,]\d{1,9})?)",
+    r"(?: ?(?P<tz>[+-]\d{2}:?\d{2}|Z|UTC))?",
+    r" +(?:(?P<thread>\S+) +)?(?P<level>ERROR|WARN|INFO|DEBUG|TRACE)",
+    r" +(?P<target>\S+)(?: +(?P<message>.*))?$"
+);
+/// The name of a log file, which could be rotated by a number or a date, and be compressed.
+const LOG_FILE_REGEX: &str =
+    r"^.+\.log(?P<rotation>\.\d+|\.\d{4}-\d{2}-\d{2})?(?P<compression>\.gz|\.zst)?$";
+const LOG_DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+/// A small buffer for probing, so a binary search does not read much.
+const PROBE_BUFFER_SIZE: usize = 4 * 1024;
+/// Bytes at the end of a log file to read for the last records at first, it grows if not enough.
+const TAIL_PROBE_SIZE: u64 = 64 * 1024;
+
+/// A log record, with the continuation lines, such as backtraces, which follow it.
+#[derive(Clone, Debug)]
+pub(crate) struct Record {
+    pub(crate) timestamp: DateTime<FixedOffset>,
+    pub(crate) thread: Option<String>,
+    pub(crate) level: Level,
+    pub(crate) target: String,
+    pub(crate) message: String,
+    /// The original text, lines are joined by "\n" without the last line break.
+    pub(crate) raw: String,
+}
+
+/// Reads records of many log files in chronological order.
+pub(crate) struct MergedReader {
+    readers: Vec<(PathBuf, RangeReader<Box<dyn BufRead>>)>,
+    heads: Vec<Option<Record>>,
+    heap: BinaryHeap<Reverse<(DateTime<FixedOffset>, usize)>>,
+    last_index: Option<usize>,
+    is_started: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// Reads records in a time range.
+pub(crate) struct RangeReader<R> {
+    records: RecordReader<R>,
+    time_range: TimeRange,
+    is_finished: bool,
+}
+
+/// Selects records by their levels, modules and messages.
+#[derive(Default)]
+pub struct RecordFilter {
+    pub(crate) level: Option<Level>,
+    pub(crate) modules: Vec<String>,
+    pub(crate) grep: Option<Regex>,
+    pub(crate) exclude: Option<Regex>,
+}
+
+/// Parses the head lines of records.
+pub(crate) struct Parser {
+    regex: Regex,
+}
+
+/// Reads records from lines.
+pub(crate) struct RecordReader<R> {
+    reader: R,
+    parser: Parser,
+    pending: Option<Record>,
+    buf: Vec<u8>,
+    is_following: bool,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        let regex = Regex::new(LOG_HEAD_REGEX).expect("compile regex");
+        Self { regex }
+    }
+}
+
+impl Parser {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a line as the head of a record, returns `None` if it is a continuation line.
+    pub(crate) fn parse_head(&self, line: &str) -> Option<Record> {
+        let caps = self.regex.captures(line)?;
+        let timestamp = parse_timestamp(&caps["datetime"], caps.name("tz").map(|m| m.as_str()))?;
+        let thread = caps.name("thread").map(|m| m.as_str().to_owned());
+        let level = caps["level"].parse().ok()?;
+        let target = caps["target"].to_owned();
+        let message = caps
+            .name("message")
+            .map(|m| m.as_str().to_owned())
+            .unwrap_or_default();
+        let raw = line.to_owned();
+        Some(Record {
+            timestamp,
+            thread,
+            level,
+            target,
+            message,
+            raw,
+        })
+    }
+}
+
+fn parse_timestamp(datetime: &str, tz: Option<&str>) -> Option<DateTime<FixedOffset>> {
+    let datetime = datetime.replace('T', " ").replace(',', ".");
+    let naive = NaiveDateTime::parse_from_str(&datetime, LOG_DATETIME_FORMAT).ok()?;
+    let offset = match tz {
+        None => {
+            return Local.from_local_datetime(&naive).earliest().map(Into::into);
+        }
+        Some("Z") | Some("UTC") => FixedOffset::east(0),
+        Some(tz) => {
+            let digits = tz[1..].replace(':', "");
+            let hours: i32 = digits.get(0..2)?.parse().ok()?;
+            let minutes: i32 = digits.get(2..4)?.parse().ok()?;
+            let seconds = hours * 3600 + minutes * 60;
+            if tz.starts_with('-') {
+                FixedOffset::west_opt(seconds)?
+            } else {
+                FixedOffset::east_opt(seconds)?
+            }
+        }
+    };
+    offset.from_local_datetime(&naive).single()
+}
+
+impl RecordFilter {
+    pub(crate) fn matches(&self, record: &Record) -> bool {
+        if let Some(level) = self.level {
+            if record.level > level {
+                return false;
+            }
+        }
+        if !self.modules.is_empty() {
+            let is_selected = self.modules.iter().any(|module| {
+                record
+                    .target
+                    .strip_prefix(module.as_str())
+                    .map(|rest| rest.is_empty() || rest.starts_with("::"))
+                    .unwrap_or(false)
+            });
+            if !is_selected {
+                return false;
+            }
+        }
+        if let Some(ref grep) = self.grep {
+            if !grep.is_match(&record.message) {
+                return false;
+            }
+        }
+        if let Some(ref exclude) = self.exclude {
+            if exclude.is_match(&record.message) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Record {
+    fn append_line(&mut self, line: &str) {
+        self.message.push('\n');
+        self.message.push_str(line);
+        self.raw.push('\n');
+        self.raw.push_str(line);
+    }
+
+    /// Converts the record into a JSON object, `file` is the name of the log file which it is
+    /// read from.
+    pub(crate) fn to_json(&self, file: &str) -> Value {
+        json!({
+            "ts": self.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, false),
+            "level": self.level.as_str(),
+            "thread": self.thread,
+            "module": self.target,
+            "message": self.message,
+            "file": file,
+        })
+    }
+}
+
+impl<R: BufRead> RecordReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            parser: Parser::new(),
+            pending: None,
+            buf: Vec::new(),
+            is_following: false,
+        }
+    }
+
+    /// Creates a reader for a file which is still being written.
+    ///
+    /// At the end of the file, an incomplete line is kept for the next read, and the last record
+    /// is kept for its continuation lines until the next record or `stop_following()`.
+    pub(crate) fn following(reader: R) -> Self {
+        Self {
+            is_following: true,
+            ..Self::new(reader)
+        }
+    }
+
+    /// The file will not be written anymore, such as it is rotated, so the rest of it, includes
+    /// the last record, is read as complete.
+    pub(crate) fn stop_following(&mut self) {
+        self.is_following = false;
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Reads the next line without the line break, invalid UTF-8 is replaced.
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        self.reader.read_until(b'\n', &mut self.buf)?;
+        if self.buf.is_empty() || (self.is_following && self.buf.last() != Some(&b'\n')) {
+            return Ok(None);
+        }
+        while let Some(b'\n') | Some(b'\r') = self.buf.last() {
+            self.buf.pop();
+        }
+        let line = String::from_utf8_lossy(&self.buf).into_owned();
+        self.buf.clear();
+        Ok(Some(line))
+    }
+}
+
+impl<R: BufRead> Iterator for RecordReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.read_line() {
+                Ok(Some(line)) => line,
+                Ok(None) if self.is_following => return None,
+                Ok(None) => return self.pending.take().map(Ok),
+                Err(err) => return Some(Err(err)),
+            };
+            if let Some(record) = self.parser.parse_head(&line) {
+                if let Some(previous) = self.pending.replace(record) {
+                    return Some(Ok(previous));
+                }
+            } else if let Some(ref mut record) = self.pending {
+                record.append_line(&line);
+            } else {
+                log::trace!("skip the line which does not belong to any record");
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for RangeReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_finished {
+            return None;
+        }
+        for result in &mut self.records {
+            match result {
+                Ok(record) if record.timestamp < self.time_range.from => continue,
+                Ok(record) if record.timestamp > self.time_range.to => break,
+                _ => return Some(result),
+            }
+        }
+        self.is_finished = true;
+        None
+    }
+}
+
+impl Compression {
+    fn of(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Some(Self::Gzip),
+            Some("zst") | Some("zstd") => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+}
+
+impl MergedReader {
+    /// Returns the log file of the last record.
+    pub(crate) fn last_file(&self) -> Option<&Path> {
+        self.last_index.map(|index| self.readers[index].0.as_path())
+    }
+
+    fn pull(&mut self, index: usize) -> io::Result<()> {
+        let (path, reader) = &mut self.readers[index];
+        match reader.next().transpose() {
+            Ok(Some(record)) => {
+                self.heap.push(Reverse((record.timestamp, index)));
+                self.heads[index] = Some(record);
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(err) => Err(io::Error::new(
+                err.kind(),
+                format!("failed to read '{}' since {}", path.display(), err),
+            )),
+        }
+    }
+}
+
+impl Iterator for MergedReader {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.is_started {
+            self.is_started = true;
+            for index in 0..self.readers.len() {
+                if let Err(err) = self.pull(index) {
+                    return Some(Err(err));
+                }
+            }
+        }
+        let Reverse((_, index)) = self.heap.pop()?;
+        let record = self.heads[index].take().expect("record of the heap");
+        self.last_index = Some(index);
+        if let Err(err) = self.pull(index) {
+            return Some(Err(err));
+        }
+        Some(Ok(record))
+    }
+}
+
+/// Lists log files in the directory, includes rotated files, such as "run.log.1" or
+/// "run.log.2021-08-01", and compressed files, such as "run.log.1.gz" or "run.log.1.zst".
+///
+/// A rotated file could be kept until its compressed copy is complete, so it is skipped if the
+/// compressed copy exists, to not read the same records twice.
+pub(crate) fn find_log_files(dir: &Path) -> Vec<PathBuf> {
+    let regex = Regex::new(LOG_FILE_REGEX).expect("compile regex");
+    let paths = WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .map(|name| regex.is_match(name))
+                .unwrap_or(false)
+        })
+        .map(|entry| entry.into_path())
+        .collect::<Vec<_>>();
+    paths
+        .iter()
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            let is_rotated = regex
+                .captures(name)
+                .map(|caps| caps.name("rotation").is_some() && caps.name("compression").is_none())
+                .unwrap_or(false);
+            let has_copy =
+                |ext: &str| paths.contains(&path.with_file_name(format!("{}.{}", name, ext)));
+            if is_rotated && (has_copy("gz") || has_copy("zst")) {
+                log::trace!("skip '{}' since its compressed copy exists", path.display());
+                return false;
+            }
+            true
+        })
+        .cloned()
+        .collect()
+}
+
+/// Reads records of all log files in the directory in the time range, in chronological order.
+pub(crate) fn open_dir_range(dir: &Path, time_range: &TimeRange) -> io::Result<MergedReader> {
+    let from = SystemTime::from(time_range.from);
+    let mut readers = Vec::new();
+    for path in find_log_files(dir) {
+        let modified = path.metadata().and_then(|metadata| metadata.modified())?;
+        // A log file which was not modified since the start does not have any record in range.
+        if modified < from {
+            log::trace!(
+                "skip '{}' since it is before the time range",
+                path.display()
+            );
+            continue;
+        }
+        let reader = open_range(&path, time_range).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to open '{}' since {}", path.display(), err),
+            )
+        })?;
+        readers.push((path, reader));
+    }
+    let heads = vec![None; readers.len()];
+    Ok(MergedReader {
+        readers,
+        heads,
+        heap: BinaryHeap::new(),
+        last_index: None,
+        is_started: false,
+    })
+}
+
+/// Returns the log file which CKB is writing into, which is the latest modified ".log" file.
+pub(crate) fn find_active_log(dir: &Path) -> Option<PathBuf> {
+    find_log_files(dir)
+        .into_iter()
+        .filter(|path| path.extension().map(|ext| ext == "log").unwrap_or(false))
+        .filter_map(|path| {
+            path.metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .map(|modified| (modified, path))
+        })
+        .max()
+        .map(|(_, path)| path)
+}
+
+/// Reads the last `count` records which match the filter, returns them with the offset after
+/// the last complete line which has been read.
+///
+/// A line which is still being written at the end of the file is left for the next read.
+pub(crate) fn read_tail(
+    path: &Path,
+    count: usize,
+    filter: &RecordFilter,
+) -> io::Result<(Vec<Record>, u64)> {
+    let mut file = File::open(path)?;
+    let len = find_line_end(&mut file)?;
+    if count == 0 {
+        return Ok((Vec::new(), len));
+    }
+    let parser = Parser::new();
+    let mut probe_size = TAIL_PROBE_SIZE;
+    loop {
+        let probe_start = len.saturating_sub(probe_size);
+        let start =
+            find_head(&mut file, &parser, probe_start)?.map_or(len, |(offset, _)| offset.min(len));
+        file.seek(SeekFrom::Start(start))?;
+        let mut records = VecDeque::with_capacity(count + 1);
+        for record in RecordReader::new(BufReader::new((&mut file).take(len - start))) {
+            let record = record?;
+            if filter.matches(&record) {
+                records.push_back(record);
+                if records.len() > count {
+                    records.pop_front();
+                }
+            }
+        }
+        if records.len() == count || probe_start == 0 {
+            return Ok((records.into_iter().collect(), len));
+        }
+        probe_size *= 4;
+    }
+}
+
+/// Opens a log file and seeks to the first record in the time range.
+///
+/// Records in a log file are in chronological order, so the start is found by a binary search
+/// on byte offsets, then only records in the time range are read.
+/// Compressed log files could not be seeked, so they are read from the start.
+pub(crate) fn open_range(
+    path: &Path,
+    time_range: &TimeRange,
+) -> io::Result<RangeReader<Box<dyn BufRead>>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn BufRead> = match Compression::of(path) {
+        Some(Compression::Gzip) => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        Some(Compression::Zstd) => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+        None => Box::new(BufReader::new(seek_to_start(file, path, time_range)?)),
+    };
+    Ok(RangeReader {
+        records: RecordReader::new(reader),
+        time_range: *time_range,
+        is_finished: false,
+    })
+}
+
+fn seek_to_start(mut file: File, path: &Path, time_range: &TimeRange) -> io::Result<File> {
+    let parser = Parser::new();
+    let len = file.metadata()?.len();
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match find_head(&mut file, &parser, mid)? {
+            Some((offset, timestamp)) if timestamp < time_range.from => lo = offset + 1,
+            _ => hi = mid,
+        }
+    }
+    let start = find_head(&mut file, &parser, lo)?.map_or(len, |(offset, _)| offset);
+    log::trace!(
+        "read '{}' from offset {} of {} bytes",
+        path.display(),
+        start,
+        len
+    );
+    file.seek(SeekFrom::Start(start))?;
+    Ok(file)
+}
+
+/// Finds the first head line which starts at or after `offset`.
+fn find_head(
+    file: &mut File,
+    parser: &Parser,
+    offset: u64,
+) -> io::Result<Option<(u64, DateTime<FixedOffset>)>> {
+    let mut position = offset.saturating_sub(1);
+    file.seek(SeekFrom::Start(position))?;
+    let mut reader = BufReader::with_capacity(PROBE_BUFFER_SIZE, file);
+    let mut buf = Vec::new();
+    if offset > 0 {
+        // Skip the rest of the line which contains the byte before `offset`.
+        position += reader.read_until(b'\n', &mut buf)? as u64;
+    }
+    loop {
+        buf.clear();
+        let size = reader.read_until(b'\n', &mut buf)?;
+        if size == 0 {
+            return Ok(None);
+        }
+        let line = String::from_utf8_lossy(&buf);
+        if let Some(record) = parser.parse_head(line.trim_end()) {
+            return Ok(Some((position, record.timestamp)));
+        }
+        position += size as u64;
+    }
+}
+
+/// Returns the offset after the last line break of the file, or 0 if there is none.
+fn find_line_end(file: &mut File) -> io::Result<u64> {
+    let mut end = file.metadata()?.len();
+    let mut buf = vec![0; PROBE_BUFFER_SIZE];
+    while end > 0 {
+        let size = end.min(buf.len() as u64);
+        let start = end - size;
+        file.seek(SeekFrom::Start(start))?;
+        let chunk = &mut buf[..size as usize];
+        file.read_exact(chunk)?;
+        if let Some(index) = chunk.iter().rposition(|&byte| byte == b'\n') {
+            return Ok(start + index as u64 + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Cursor, Write as _};
+
+    use flate2::write::GzEncoder;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn datetime(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    /// Records in one hour, one per second, every tenth one has a backtrace.
+    fn sample_log() -> String {
+        let start = datetime("2021-08-20T10:00:00+00:00");
+        let mut log = String::new();
+        for index in 0..3600 {
+            let timestamp = start + chrono::Duration::seconds(index);
+            log.push_str(&format!(
+                "{} main INFO ckb_sync::synchronizer  record {}\n",
+                timestamp.format("%Y-%m-%d %H:%M:%S%.3f %:z"),
+                index
+            ));
+            if index % 10 == 0 {
+                log.push_str("   0: backtrace line\n   1: another line\n");
+            }
+        }
+        log
+    }
+
+    fn read_range(path: &Path, from: &str, to: &str) -> Vec<Record> {
+        let time_range = TimeRange::new(datetime(from), datetime(to)).unwrap();
+        open_range(path, &time_range)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_head_of_current_format() {
+        let line = "2021-08-20 10:00:00.123 +08:00 ChainService ERROR ckb_chain::chain  \
+                    block 0x00 is invalid";
+        let record = Parser::new().parse_head(line).unwrap();
+        assert_eq!(record.timestamp, datetime("2021-08-20T10:00:00.123+08:00"));
+        assert_eq!(record.thread.as_deref(), Some("ChainService"));
+        assert_eq!(record.level, Level::Error);
+        assert_eq!(record.target, "ckb_chain::chain");
+        assert_eq!(record.message, "block 0x00 is invalid");
+        assert_eq!(record.raw, line);
+    }
+
+    #[test]
+    fn parse_head_of_legacy_formats() {
+        let parser = Parser::new();
+        let cases = [
+            // Without the thread.
+            (
+                "2019-11-16 10:00:00.123 +08:00 INFO ckb_bin  started",
+                "2019-11-16T10:00:00.123+08:00",
+                None,
+            ),
+            // With "T", "," and a timezone without ":".
+            (
+                "2019-11-16T10:00:00,123+0800 main WARN ckb_network  slow",
+                "2019-11-16T10:00:00.123+08:00",
+                Some("main"),
+            ),
+            (
+                "2019-11-16 02:00:00 UTC main DEBUG ckb_db  opened",
+                "2019-11-16T02:00:00+00:00",
+                Some("main"),
+            ),
+            (
+                "2019-11-16 02:00:00.5Z INFO ckb_db  opened",
+                "2019-11-16T02:00:00.5+00:00",
+                None,
+            ),
+        ];
+        for (line, timestamp, thread) in &cases {
+            let record = parser.parse_head(line).expect(line);
+            assert_eq!(record.timestamp, datetime(timestamp), "{}", line);
+            assert_eq!(record.thread.as_deref(), *thread, "{}", line);
+        }
+    }
+
+    #[test]
+    fn parse_head_without_timezone_in_local_time() {
+        let record = Parser::new()
+            .parse_head("2019-11-16 10:00:00.000 main INFO ckb_bin  started")
+            .unwrap();
+        let expected = Local
+            .from_local_datetime(
+                &NaiveDateTime::parse_from_str("2019-11-16 10:00:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+            )
+            .earliest()
+            .unwrap();
+        assert_eq!(record.timestamp, expected);
+    }
+
+    #[test]
+    fn continuation_lines_are_not_heads() {
+        let parser = Parser::new();
+        for line in &[
+            "   0: backtrace line",
+            "thread 'main' panicked at 'boom'",
+            "",
+            "2019-11-16 10:00:00.000 main NOTICE ckb_bin  unknown level",
+        ] {
+            assert!(parser.parse_head(line).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn read_records_with_continuation_lines() {
+        let log = "orphan line before any record\n\
+                   2021-08-20 10:00:00.000 +00:00 main ERROR ckb_chain  panicked\n\
+                   \x20  0: backtrace line\n\
+                   \x20  1: another line\n\
+                   2021-08-20 10:00:01.000 +00:00 main INFO ckb_chain  recovered";
+        let records = RecordReader::new(Cursor::new(log))
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].message,
+            "panicked\n   0: backtrace line\n   1: another line"
+        );
+        assert!(records[0].raw.ends_with("\n   1: another line"));
+        assert_eq!(records[1].message, "recovered");
+    }
+
+    #[test]
+    fn keep_the_incomplete_line_when_following() {
+        let log = "2021-08-20 10:00:00.000 +00:00 main INFO ckb_bin  first\n\
+                   2021-08-20 10:00:01.000 +00:00 main INFO ckb_bin  sec";
+        let mut reader = RecordReader::following(Cursor::new(log));
+        // The last record is kept for its continuation lines.
+        assert!(reader.next().is_none());
+        reader.stop_following();
+        let records = reader.collect::<io::Result<Vec<_>>>().unwrap();
+        let messages = records
+            .iter()
+            .map(|record| record.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, ["first", "sec"]);
+    }
+
+    #[test]
+    fn read_tail_before_the_incomplete_line() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("run.log");
+        let complete = "2021-08-20 10:00:00.000 +00:00 main INFO ckb_bin  first\n\
+                        2021-08-20 10:00:01.000 +00:00 main INFO ckb_bin  second\n";
+        fs::write(
+            &path,
+            format!("{}2021-08-20 10:00:02.000 +00:00 main INFO ckb", complete),
+        )
+        .unwrap();
+
+        let (records, offset) = read_tail(&path, 2, &RecordFilter::default()).unwrap();
+        let messages = records
+            .iter()
+            .map(|record| record.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, ["first", "second"]);
+        assert_eq!(offset, complete.len() as u64);
+
+        fs::write(&path, "2021-08-20 10:00:00.000 +00:00 main INFO ckb").unwrap();
+        let (records, offset) = read_tail(&path, 2, &RecordFilter::default()).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(offset, 0);
+    }
+
+    #[test]
+    fn find_log_files_by_names() {
+        let tmp_dir = TempDir::new().unwrap();
+        for name in &[
+            "run.log",
+            "run.log.1",
+            "run.log.2",
+            "run.log.2.gz",
+            "run.log.2021-08-01",
+            "run.log.2021-08-02.zst",
+            "run.log.bak",
+            "run.log.swp",
+            "run.log.lock",
+            "my.log.config",
+            "run.txt",
+        ] {
+            fs::write(tmp_dir.path().join(name), "").unwrap();
+        }
+        let names = find_log_files(tmp_dir.path())
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "run.log",
+                "run.log.1",
+                "run.log.2.gz",
+                "run.log.2021-08-01",
+                "run.log.2021-08-02.zst",
+            ]
+        );
+    }
+
+    #[test]
+    fn open_range_by_binary_search() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("run.log");
+        fs::write(&path, sample_log()).unwrap();
+
+        let records = read_range(
+            &path,
+            "2021-08-20T10:20:00+00:00",
+            "2021-08-20T10:30:00+00:00",
+        );
+        assert_eq!(records.len(), 601);
+        assert_eq!(
+            records[0].message,
+            "record 1200\n   0: backtrace line\n   1: another line"
+        );
+        assert_eq!(
+            records[600].message,
+            "record 1800\n   0: backtrace line\n   1: another line"
+        );
+
+        // A start between records.
+        let records = read_range(
+            &path,
+            "2021-08-20T10:20:00.5+00:00",
+            "2021-08-20T10:20:02+00:00",
+        );
+        let messages = records
+            .iter()
+            .map(|record| record.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, ["record 1201", "record 1202"]);
+    }
+
+    #[test]
+    fn open_range_at_edges() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("run.log");
+        fs::write(&path, sample_log()).unwrap();
+
+        let all = read_range(
+            &path,
+            "2021-08-20T09:00:00+00:00",
+            "2021-08-20T12:00:00+00:00",
+        );
+        assert_eq!(all.len(), 3600);
+        assert!(all[0].message.starts_with("record 0\n"));
+        assert_eq!(all[3599].message, "record 3599");
+
+        let before = read_range(
+            &path,
+            "2021-08-20T08:00:00+00:00",
+            "2021-08-20T09:00:00+00:00",
+        );
+        assert!(before.is_empty());
+        let after = read_range(
+            &path,
+            "2021-08-20T12:00:00+00:00",
+            "2021-08-20T13:00:00+00:00",
+        );
+        assert!(after.is_empty());
+    }
+
+    #[test]
+    fn open_range_of_compressed_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("run.log.1.gz");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Default::default());
+        encoder.write_all(sample_log().as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let records = read_range(
+            &path,
+            "2021-08-20T10:59:58+00:00",
+            "2021-08-20T11:00:00+00:00",
+        );
+        let messages = records
+            .iter()
+            .map(|record| record.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, ["record 3598", "record 3599"]);
+    }
+}
@@ -0,0 +1,44 @@
+use std::process::{Command, Stdio};
+
+use crate::error::{Error, Result};
+
+/// Runs the command with inherited stdout and fails if it does not exit successfully.
+pub(crate) fn run(command: &mut Command) -> Result<()> {
+    run_with_stdout(command, Stdio::inherit())
+}
+
+/// Runs the command with the given stdout and fails if it does not exit successfully.
+pub(crate) fn run_with_stdout(command: &mut Command, stdout: Stdio) -> Result<()> {
+    let output = command.stdout(stdout).output().map_err(|err| {
+        let msg = format!("failed to execute `{:?}` since {}", command, err);
+        Error::Exec(msg)
+    })?;
+    if !output.status.success() {
+        let msg = format!(
+            "failed to execute `{:?}` since {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(Error::Exec(msg));
+    }
+    Ok(())
+}
+
+/// Runs the command and returns its stdout if it exits successfully.
+pub(crate) fn output(command: &mut Command) -> Result<String> {
+    let output = command.output().map_err(|err| {
+        let msg = format!("failed to execute `{:?}` since {}", command, err);
+        Error::Exec(msg)
+    })?;
+    if !output.status.success() {
+        let msg = format!(
+            "failed to execute `{:?}` since {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(Error::Exec(msg));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
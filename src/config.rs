@@ -1,15 +1,23 @@
 use std::{net::IpAddr, path::PathBuf, str::FromStr as _};
 
-use ini::Ini;
+use age::x25519;
+use ini::{Ini, Properties};
 use url::Url;
 
 use crate::{
+    catalog::{KeyTemplate, DEFAULT_KEY_TEMPLATE},
     ckb_config::AppConfig as CkbConfig,
     error::{Error, Result},
 };
 
 const NORMAL_CONFIG_FILE: &str = "/etc/ckbdev.conf";
 const SECRET_CONFIG_FILE: &str = "/etc/ckbdev.secret.conf";
+/// The region of Qiniu buckets if it is not set, which is East China.
+const DEFAULT_QINIU_REGION: &str = "z0";
+/// Hosts of APIs to manage and list objects of buckets in any region, they are used if
+/// `[qiniu.upload_hosts]` is set instead of the region.
+const QINIU_RS_URL: &str = "https://rs.qbox.me";
+const QINIU_RSF_URL: &str = "https://rsf.qbox.me";
 
 pub struct Config {
     pub(crate) normal: NormalConfig,
@@ -22,7 +30,11 @@ pub(crate) struct NormalConfig {
 }
 
 pub(crate) struct SecretConfig {
-    pub(crate) qiniu: QiniuSection,
+    pub(crate) storage: StorageSection,
+    /// The template of keys of uploaded archives, it is `[storage.key_template]`.
+    pub(crate) key_template: KeyTemplate,
+    pub(crate) encryption: Option<EncryptionSection>,
+    pub(crate) redact: RedactSection,
 }
 
 pub(crate) struct HostSection {
@@ -45,6 +57,50 @@ pub(crate) struct QiniuSection {
     pub(crate) bucket: String,
     pub(crate) domain: Url,
     pub(crate) path_prefix: String,
+    /// Download URLs of a private bucket are signed, and they expire.
+    pub(crate) private: bool,
+    /// Hosts to upload objects to, they are tried in order if some of them fail.
+    pub(crate) upload_urls: Vec<Url>,
+    /// The host of APIs to manage objects.
+    pub(crate) rs_url: Url,
+    /// The host of APIs to list objects.
+    pub(crate) rsf_url: Url,
+}
+
+/// The backend which archives are uploaded to, it is selected by `[storage.backend]`.
+pub(crate) enum StorageSection {
+    Qiniu(QiniuSection),
+    Local(LocalSection),
+    S3(S3Section),
+}
+
+/// A local directory, it could be a mounted NFS.
+pub(crate) struct LocalSection {
+    pub(crate) dir: PathBuf,
+}
+
+/// An S3-compatible object storage, such as AWS S3 or MinIO.
+pub(crate) struct S3Section {
+    pub(crate) endpoint: Url,
+    pub(crate) region: String,
+    pub(crate) bucket: String,
+    pub(crate) access_key: String,
+    pub(crate) secret_key: String,
+    pub(crate) path_prefix: String,
+    /// Put the bucket in the path of URLs instead of the host name, MinIO requires it.
+    pub(crate) path_style: bool,
+}
+
+/// How archives are encrypted before uploaded.
+pub(crate) enum EncryptionSection {
+    /// Public keys of age, only the owners of their private keys can decrypt archives.
+    Recipients(Vec<x25519::Recipient>),
+    Passphrase(String),
+}
+
+/// Extra secrets to mask when redacting backups.
+pub(crate) struct RedactSection {
+    pub(crate) secrets: Vec<String>,
 }
 
 impl Config {
@@ -120,49 +176,270 @@ impl SecretConfig {
     pub(crate) fn load_from_file(path: &str) -> Result<Self> {
         let ini = Ini::load_from_file(path)
             .map_err(|err| Error::Cfg(format!("failed to load \"{}\" since {}", path, err)))?;
-        let qiniu = {
-            let prop = ini
-                .section(Some("qiniu"))
-                .ok_or_else(|| Error::config_not_found("qiniu"))?;
-            let access_key = prop
-                .get("access_key")
-                .ok_or_else(|| Error::config_not_found("qiniu.access_key"))?
-                .to_owned();
-            let secret_key = prop
-                .get("secret_key")
-                .ok_or_else(|| Error::config_not_found("qiniu.secret_key"))?
-                .to_owned();
-            let bucket = prop
-                .get("bucket")
-                .ok_or_else(|| Error::config_not_found("qiniu.bucket"))?
-                .to_owned();
-            let domain = prop
-                .get("domain")
-                .ok_or_else(|| Error::config_not_found("qiniu.domain"))
-                .and_then(|s| {
-                    let u = Url::parse(s).map_err(|err| {
-                        Error::Cfg(format!("failed to parse [qiniu.domain] since {}", err))
-                    })?;
-                    if u.scheme() != "http" && u.scheme() != "https" {
-                        let msg = "invalid [qiniu.domain], scheme should be \"http\" or \"https\"";
-                        let err = Error::Cfg(msg.to_owned());
-                        Err(err)
-                    } else {
-                        Ok(u)
-                    }
-                })?;
-            let path_prefix = prop
-                .get("path_prefix")
-                .ok_or_else(|| Error::config_not_found("qiniu.path_prefix"))?
-                .to_owned();
-            QiniuSection {
-                access_key,
-                secret_key,
-                bucket,
-                domain,
-                path_prefix,
+        let backend = ini
+            .section(Some("storage"))
+            .and_then(|prop| prop.get("backend"))
+            .unwrap_or("qiniu");
+        let storage = match backend {
+            "qiniu" => StorageSection::Qiniu(QiniuSection::load(&ini)?),
+            "local" => StorageSection::Local(LocalSection::load(&ini)?),
+            "s3" => StorageSection::S3(S3Section::load(&ini)?),
+            _ => {
+                let msg = format!(
+                    "invalid [storage.backend] \"{}\", it should be \"qiniu\", \"local\" or \"s3\"",
+                    backend
+                );
+                return Err(Error::Cfg(msg));
+            }
+        };
+        let key_template = ini
+            .section(Some("storage"))
+            .and_then(|prop| prop.get("key_template"))
+            .filter(|s| !s.is_empty())
+            .unwrap_or(DEFAULT_KEY_TEMPLATE);
+        let key_template = KeyTemplate::parse(key_template)?;
+        let encryption = ini
+            .section(Some("encryption"))
+            .map(EncryptionSection::load)
+            .transpose()?
+            .flatten();
+        let redact = {
+            let secrets = ini
+                .section(Some("redact"))
+                .and_then(|prop| prop.get("secrets"))
+                .map(|s| {
+                    s.split(',')
+                        .map(str::trim)
+                        .filter(|secret| !secret.is_empty())
+                        .map(ToOwned::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default();
+            RedactSection { secrets }
+        };
+        Ok(Self {
+            storage,
+            key_template,
+            encryption,
+            redact,
+        })
+    }
+
+    /// All secrets which should not be in backups.
+    pub(crate) fn secrets(&self) -> Vec<&str> {
+        let mut secrets = match &self.storage {
+            StorageSection::Qiniu(qiniu) => {
+                vec![qiniu.access_key.as_str(), qiniu.secret_key.as_str()]
             }
+            StorageSection::Local(_) => Vec::new(),
+            StorageSection::S3(s3) => vec![s3.access_key.as_str(), s3.secret_key.as_str()],
         };
-        Ok(Self { qiniu })
+        if let Some(EncryptionSection::Passphrase(passphrase)) = &self.encryption {
+            secrets.push(passphrase.as_str());
+        }
+        secrets.extend(self.redact.secrets.iter().map(String::as_str));
+        secrets
+    }
+}
+
+impl QiniuSection {
+    fn load(ini: &Ini) -> Result<Self> {
+        let prop = ini
+            .section(Some("qiniu"))
+            .ok_or_else(|| Error::config_not_found("qiniu"))?;
+        let access_key = required(prop, "qiniu", "access_key")?;
+        let secret_key = required(prop, "qiniu", "secret_key")?;
+        let bucket = required(prop, "qiniu", "bucket")?;
+        let domain = prop
+            .get("domain")
+            .ok_or_else(|| Error::config_not_found("qiniu.domain"))
+            .and_then(|s| parse_http_url(s, "qiniu.domain"))?;
+        let path_prefix = required(prop, "qiniu", "path_prefix")?;
+        let private = parse_bool(prop, "qiniu", "private", false)?;
+        let region = prop.get("region").filter(|s| !s.is_empty());
+        let upload_hosts = prop.get("upload_hosts").filter(|s| !s.is_empty());
+        let (upload_urls, rs_url, rsf_url) = match (region, upload_hosts) {
+            (Some(_), Some(_)) => {
+                let msg = "both [qiniu.region] and [qiniu.upload_hosts] are set, \
+                    only one of them is allowed";
+                return Err(Error::Cfg(msg.to_owned()));
+            }
+            (region, None) => {
+                let region = region.unwrap_or(DEFAULT_QINIU_REGION);
+                (
+                    qiniu_upload_urls(region)?,
+                    qiniu_api_url("rs", region),
+                    qiniu_api_url("rsf", region),
+                )
+            }
+            (None, Some(upload_hosts)) => {
+                let upload_urls = upload_hosts
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(|s| parse_http_url(s, "qiniu.upload_hosts"))
+                    .collect::<Result<Vec<_>>>()?;
+                let rs_url = Url::parse(QINIU_RS_URL).expect("parse the RS URL");
+                let rsf_url = Url::parse(QINIU_RSF_URL).expect("parse the RSF URL");
+                (upload_urls, rs_url, rsf_url)
+            }
+        };
+        if upload_urls.is_empty() {
+            return Err(Error::config_not_found("qiniu.upload_hosts"));
+        }
+        Ok(Self {
+            access_key,
+            secret_key,
+            bucket,
+            domain,
+            path_prefix,
+            private,
+            upload_urls,
+            rs_url,
+            rsf_url,
+        })
+    }
+}
+
+/// Upload hosts of the region, the accelerated one is the first.
+///
+/// Ref: <https://developer.qiniu.com/kodo/1671/region-endpoint-fq>
+fn qiniu_upload_urls(region: &str) -> Result<Vec<Url>> {
+    let hosts: &[&str] = match region {
+        "z0" => &["upload.qiniup.com", "up.qiniup.com"],
+        "cn-east-2" => &["upload-cn-east-2.qiniup.com", "up-cn-east-2.qiniup.com"],
+        "z1" => &["upload-z1.qiniup.com", "up-z1.qiniup.com"],
+        "z2" => &["upload-z2.qiniup.com", "up-z2.qiniup.com"],
+        "na0" => &["upload-na0.qiniup.com", "up-na0.qiniup.com"],
+        "as0" => &["upload-as0.qiniup.com", "up-as0.qiniup.com"],
+        _ => {
+            let msg = format!(
+                "invalid [qiniu.region] \"{}\", it should be one of \"z0\", \"cn-east-2\", \
+                \"z1\", \"z2\", \"na0\" and \"as0\"",
+                region
+            );
+            return Err(Error::Cfg(msg));
+        }
+    };
+    let urls = hosts
+        .iter()
+        .map(|host| Url::parse(&format!("https://{}", host)).expect("parse the upload URL"))
+        .collect();
+    Ok(urls)
+}
+
+/// The host of APIs of the region, `service` is "rs" to manage objects or "rsf" to list them.
+///
+/// Ref: <https://developer.qiniu.com/kodo/1671/region-endpoint-fq>
+fn qiniu_api_url(service: &str, region: &str) -> Url {
+    Url::parse(&format!("https://{}-{}.qiniuapi.com", service, region)).expect("parse the API URL")
+}
+
+impl LocalSection {
+    fn load(ini: &Ini) -> Result<Self> {
+        let prop = ini
+            .section(Some("local"))
+            .ok_or_else(|| Error::config_not_found("local"))?;
+        let dir = required(prop, "local", "dir").map(PathBuf::from)?;
+        if !dir.is_absolute() {
+            let msg = format!(
+                "invalid [local.dir] \"{}\", it should be absolute",
+                dir.display()
+            );
+            return Err(Error::Cfg(msg));
+        }
+        Ok(Self { dir })
+    }
+}
+
+impl S3Section {
+    fn load(ini: &Ini) -> Result<Self> {
+        let prop = ini
+            .section(Some("s3"))
+            .ok_or_else(|| Error::config_not_found("s3"))?;
+        let endpoint = prop
+            .get("endpoint")
+            .ok_or_else(|| Error::config_not_found("s3.endpoint"))
+            .and_then(|s| parse_http_url(s, "s3.endpoint"))?;
+        let region = prop.get("region").unwrap_or("us-east-1").to_owned();
+        let bucket = required(prop, "s3", "bucket")?;
+        let access_key = required(prop, "s3", "access_key")?;
+        let secret_key = required(prop, "s3", "secret_key")?;
+        let path_prefix = prop.get("path_prefix").unwrap_or_default().to_owned();
+        let path_style = parse_bool(prop, "s3", "path_style", true)?;
+        Ok(Self {
+            endpoint,
+            region,
+            bucket,
+            access_key,
+            secret_key,
+            path_prefix,
+            path_style,
+        })
+    }
+}
+
+impl EncryptionSection {
+    /// Archives are not encrypted if neither recipients nor a passphrase is set.
+    fn load(prop: &Properties) -> Result<Option<Self>> {
+        let recipients = prop
+            .get("recipients")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                x25519::Recipient::from_str(s).map_err(|err| {
+                    let msg = format!(
+                        "failed to parse [encryption.recipients] \"{}\" since {}",
+                        s, err
+                    );
+                    Error::Cfg(msg)
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let passphrase = prop.get("passphrase").unwrap_or_default();
+        match (recipients.is_empty(), passphrase.is_empty()) {
+            (true, true) => Ok(None),
+            (false, true) => Ok(Some(Self::Recipients(recipients))),
+            (true, false) => Ok(Some(Self::Passphrase(passphrase.to_owned()))),
+            (false, false) => {
+                let msg = "both [encryption.recipients] and [encryption.passphrase] are set, \
+                    only one of them is allowed";
+                Err(Error::Cfg(msg.to_owned()))
+            }
+        }
+    }
+}
+
+fn required(prop: &Properties, section: &str, key: &str) -> Result<String> {
+    prop.get(key)
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| Error::config_not_found(&format!("{}.{}", section, key)))
+}
+
+fn parse_bool(prop: &Properties, section: &str, key: &str, default: bool) -> Result<bool> {
+    match prop.get(key) {
+        None => Ok(default),
+        Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(s) => {
+            let msg = format!(
+                "invalid [{}.{}] \"{}\", it should be \"true\" or \"false\"",
+                section, key, s
+            );
+            Err(Error::Cfg(msg))
+        }
+    }
+}
+
+fn parse_http_url(s: &str, key: &str) -> Result<Url> {
+    let u = Url::parse(s)
+        .map_err(|err| Error::Cfg(format!("failed to parse [{}] since {}", key, err)))?;
+    if u.scheme() != "http" && u.scheme() != "https" {
+        let msg = format!("invalid [{}], scheme should be \"http\" or \"https\"", key);
+        Err(Error::Cfg(msg))
+    } else {
+        Ok(u)
     }
 }
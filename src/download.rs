@@ -0,0 +1,32 @@
+use std::{fs::File, path::Path};
+
+use reqwest::blocking::Client;
+use url::Url;
+
+use crate::error::{Error, Result};
+
+/// Downloads the content of the URL into a file.
+pub(crate) fn download(url: &Url, dst_path: &Path) -> Result<()> {
+    // Downloading a large snapshot takes a long time.
+    let client = Client::builder().timeout(None).build().map_err(|err| {
+        let msg = format!("failed to create the HTTP client since {}", err);
+        Error::Exec(msg)
+    })?;
+    let mut response = client
+        .get(url.clone())
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| {
+            let msg = format!("failed to download '{}' since {}", url, err);
+            Error::Exec(msg)
+        })?;
+    let mut file = File::create(dst_path).map_err(|err| {
+        let msg = format!("failed to create '{}' since {}", dst_path.display(), err);
+        Error::Exec(msg)
+    })?;
+    response.copy_to(&mut file).map_err(|err| {
+        let msg = format!("failed to download '{}' since {}", url, err);
+        Error::Exec(msg)
+    })?;
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write as _},
+    path::Path,
+};
+
+use age::{x25519, Decryptor, Encryptor, IdentityFile, Recipient};
+use secrecy::SecretString;
+
+use crate::{
+    config::EncryptionSection,
+    error::{Error, Result},
+};
+
+/// The extension which is appended to names of encrypted files.
+pub(crate) const EXTENSION: &str = "age";
+/// Encrypted files start with the header of the age format.
+pub(crate) const MAGIC: &[u8] = b"age-encryption.org/";
+
+/// Where the key to decrypt files comes from.
+pub(crate) enum Secret<'a> {
+    IdentityFile(&'a Path),
+    PassphraseFile(&'a Path),
+    Passphrase(&'a str),
+    /// Ask the passphrase on the terminal.
+    Prompt,
+}
+
+/// Encrypts a file into the age format, with recipients or a passphrase.
+pub(crate) fn encrypt_file(
+    cfg: &EncryptionSection,
+    src_path: &Path,
+    dst_path: &Path,
+) -> Result<()> {
+    let encryptor = match cfg {
+        EncryptionSection::Recipients(recipients) => {
+            let recipients = recipients
+                .iter()
+                .cloned()
+                .map(|recipient| Box::new(recipient) as Box<dyn Recipient>)
+                .collect();
+            Encryptor::with_recipients(recipients)
+        }
+        EncryptionSection::Passphrase(passphrase) => {
+            Encryptor::with_user_passphrase(SecretString::new(passphrase.to_owned()))
+        }
+    };
+    let mut read_file = open(src_path)?;
+    let write_file = create(dst_path)?;
+    let mut writer = encryptor.wrap_output(write_file).map_err(|err| {
+        let msg = format!("failed to encrypt '{}' since {}", src_path.display(), err);
+        Error::Exec(msg)
+    })?;
+    io::copy(&mut read_file, &mut writer)
+        .and_then(|_| writer.finish())
+        .and_then(|mut write_file| write_file.flush())
+        .map_err(|err| {
+            let _ = fs::remove_file(dst_path);
+            let msg = format!(
+                "failed to encrypt '{}' into '{}' since {}",
+                src_path.display(),
+                dst_path.display(),
+                err
+            );
+            Error::Exec(msg)
+        })
+}
+
+/// Decrypts a file in the age format.
+///
+/// Files encrypted to recipients require an identity file, and others require a passphrase.
+pub(crate) fn decrypt_file(src_path: &Path, dst_path: &Path, secret: &Secret) -> Result<()> {
+    let decryptor = Decryptor::new(open(src_path)?).map_err(|err| {
+        let msg = format!("failed to read '{}' since {}", src_path.display(), err);
+        Error::Exec(msg)
+    })?;
+    let mut reader = match decryptor {
+        Decryptor::Recipients(decryptor) => {
+            let path = match secret {
+                Secret::IdentityFile(path) => path,
+                _ => {
+                    let msg = format!(
+                        "'{}' is encrypted to recipients, the identity file is required",
+                        src_path.display()
+                    );
+                    return Err(Error::Arg(msg));
+                }
+            };
+            let identities = load_identities(path)?;
+            decryptor.decrypt(
+                identities
+                    .iter()
+                    .map(|identity| identity as &dyn age::Identity),
+            )
+        }
+        Decryptor::Passphrase(decryptor) => {
+            let passphrase = match secret {
+                Secret::IdentityFile(_) => {
+                    let msg = format!(
+                        "'{}' is encrypted with a passphrase, not to recipients",
+                        src_path.display()
+                    );
+                    return Err(Error::Arg(msg));
+                }
+                Secret::PassphraseFile(path) => read_passphrase_file(path)?,
+                Secret::Passphrase(passphrase) => (*passphrase).to_owned(),
+                Secret::Prompt => {
+                    rpassword::read_password_from_tty(Some("Passphrase: ")).map_err(|err| {
+                        let msg = format!("failed to read the passphrase since {}", err);
+                        Error::Exec(msg)
+                    })?
+                }
+            };
+            decryptor.decrypt(&SecretString::new(passphrase), None)
+        }
+    }
+    .map_err(|err| {
+        let msg = format!("failed to decrypt '{}' since {}", src_path.display(), err);
+        Error::Exec(msg)
+    })?;
+    let mut write_file = create(dst_path)?;
+    // The data is authenticated chunk by chunk, so remove the plaintext if any chunk is bad.
+    io::copy(&mut reader, &mut write_file)
+        .and_then(|_| write_file.flush())
+        .map_err(|err| {
+            let _ = fs::remove_file(dst_path);
+            let msg = format!(
+                "failed to decrypt '{}' into '{}' since {}",
+                src_path.display(),
+                dst_path.display(),
+                err
+            );
+            Error::Exec(msg)
+        })?;
+    Ok(())
+}
+
+fn load_identities(path: &Path) -> Result<Vec<x25519::Identity>> {
+    let identities = IdentityFile::from_file(path.to_string_lossy().into_owned())
+        .map(IdentityFile::into_identities)
+        .map_err(|err| {
+            let msg = format!(
+                "failed to load identities from '{}' since {}",
+                path.display(),
+                err
+            );
+            Error::Exec(msg)
+        })?;
+    if identities.is_empty() {
+        let msg = format!("no identities in '{}'", path.display());
+        return Err(Error::Exec(msg));
+    }
+    Ok(identities)
+}
+
+fn read_passphrase_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .map(|content| content.trim_end_matches(&['\r', '\n'][..]).to_owned())
+        .map_err(|err| {
+            let msg = format!("failed to read '{}' since {}", path.display(), err);
+            Error::Exec(msg)
+        })
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    File::open(path).map(BufReader::new).map_err(|err| {
+        let msg = format!("failed to open '{}' since {}", path.display(), err);
+        Error::Exec(msg)
+    })
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map(BufWriter::new)
+        .map_err(|err| {
+            let msg = format!("failed to create '{}' since {}", path.display(), err);
+            Error::Exec(msg)
+        })
+}
@@ -12,6 +12,12 @@ pub enum Error {
     Rpc(String),
     #[error("qiniu error: {0}")]
     Qiniu(String),
+    #[error("storage error: {0}")]
+    Storage(String),
+    /// The request could succeed if it is sent again, such as a timeout, a server error or
+    /// throttling.
+    #[error("network error: {0}")]
+    Network(String),
 }
 
 pub(crate) type Result<T> = ::std::result::Result<T, Error>;
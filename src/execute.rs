@@ -1,28 +1,49 @@
 use std::{
-    fs::OpenOptions,
-    io::{prelude::*, BufReader, SeekFrom},
+    collections::BTreeMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, prelude::*, BufReader, BufWriter, SeekFrom},
+    os::unix::fs::{MetadataExt as _, PermissionsExt as _},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    thread,
+    time::{Duration as StdDuration, Instant},
 };
 
-use chrono::{DateTime, Utc};
-use fs_extra::dir;
-use regex::Regex;
+use chrono::{Duration, Utc};
+use log::Level;
 use serde_json::{json, to_string_pretty};
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
 use crate::{
-    argument::{Args, BackupArgs, L1Args, L2Args, RpcArgs},
-    config::Config,
+    archive::{self, ArchiveWriter, Compression},
+    argument::{
+        ArchiveOutput, ArchiveSource, Args, BackupArgs, BackupDiagArgs, BackupFilesArgs, ColorMode,
+        DecryptArgs, ExportFormat, L1Args, L2Args, LogsArgs, RestoreArgs, RpcArgs,
+    },
+    catalog,
+    ckb_cli::CkbCli,
+    ckb_log::{self, MergedReader, Record, RecordFilter, RecordReader},
+    command,
+    config::{Config, EncryptionSection},
+    download,
+    encryption::{self, Secret},
     error::{Error, Result},
-    qiniu,
+    hash, host,
+    log_summary::Summary,
+    manifest::{Manifest, TimeWindow, MANIFEST_FILE_NAME},
+    prompt,
+    redact::Redactor,
     rpc_client::RpcClient,
+    service::Service,
+    storage::{self, Metadata},
+    time_range::TimeRange,
+    upload,
 };
 
-const LOG_TIMESTAMP_REGEX: &str =
-    r"^\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}([.]\d{1,3}) [+-]\d{2}:\d{2} ";
-const LOG_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S.%f %:z";
-const LOG_MIN_TAIL_CHECK: usize = 200;
+const HEALTH_CHECK_INTERVAL: StdDuration = StdDuration::from_secs(2);
+const FOLLOW_INTERVAL: StdDuration = StdDuration::from_millis(500);
 
 pub trait CanExecute {
     fn execute(&self, cfg: &Config) -> Result<()>;
@@ -34,11 +55,29 @@ impl CanExecute for Args {
             Self::L1(inner) => inner.execute(cfg),
             Self::L2(inner) => inner.execute(cfg),
             Self::Backup(inner) => inner.execute(cfg),
+            Self::Logs(inner) => inner.execute(cfg),
+            Self::Restore(inner) => inner.execute(cfg),
+            Self::Decrypt(inner) => inner.execute_without_config(),
             Self::Rpc(inner) => inner.execute(cfg),
         }
     }
 }
 
+/// Commands which could run on machines without the config.
+pub trait CanExecuteWithoutConfig {
+    fn execute_without_config(&self) -> Result<()>;
+}
+
+impl CanExecuteWithoutConfig for Args {
+    fn execute_without_config(&self) -> Result<()> {
+        match self {
+            Self::Decrypt(inner) => inner.execute_without_config(),
+            _ => Err(Error::Cfg("the command requires the config".to_owned())),
+        }
+    }
+}
+
+#[allow(unknown_lints, clippy::needless_borrows_for_generic_args)]
 impl CanExecute for L1Args {
     fn execute(&self, cfg: &Config) -> Result<()> {
         let mut command = match self {
@@ -62,27 +101,544 @@ impl CanExecute for L1Args {
                 command.args(&["status", &cfg.normal.ckb.service_name]);
                 command
             }
-            Self::ResetData { peer_store } => {
-                let ckb_bin_path = cfg.normal.ckb.bin_path.to_str().expect("ckb.bin_path");
-                let ckb_root_dir = cfg.normal.ckb.root_dir.to_str().expect("ckb.root_dir");
-                let mut command = Command::new(ckb_bin_path);
-                command.args(&["reset-data", "--force", "-C", ckb_root_dir]);
-                if *peer_store {
-                    command.arg("--network-peer-store");
-                } else {
-                    command.arg("--all");
-                }
-                command
+            Self::ResetData {
+                peer_store,
+                stop_first,
+                yes,
+                backup_dir,
+                backup_upload,
+            } => {
+                return reset_data(
+                    cfg,
+                    *peer_store,
+                    *stop_first,
+                    *yes,
+                    backup_dir.as_deref(),
+                    *backup_upload,
+                );
+            }
+            Self::Upgrade {
+                binary,
+                sha256,
+                no_checksum,
+                timeout,
+                snapshot_dir,
+                yes,
+            } => {
+                return upgrade(
+                    cfg,
+                    binary,
+                    sha256.as_deref(),
+                    *no_checksum,
+                    *timeout,
+                    snapshot_dir.as_deref(),
+                    *yes,
+                );
+            }
+            Self::Migrate {
+                check,
+                snapshot_dir,
+                yes,
+            } => {
+                return migrate(cfg, *check, snapshot_dir.as_deref(), *yes);
+            }
+            Self::Rollback { timeout } => {
+                return rollback(cfg, *timeout);
             }
         };
-        command.stdout(Stdio::inherit()).output().map_err(|err| {
-            let msg = format!("failed to execute `{:?}` since {}", command, err);
-            Error::Exec(msg)
-        })?;
+        command::run(&mut command)
+    }
+}
+
+fn reset_data(
+    cfg: &Config,
+    peer_store: bool,
+    stop_first: bool,
+    yes: bool,
+    backup_dir: Option<&Path>,
+    backup_upload: bool,
+) -> Result<()> {
+    let ckb = &cfg.normal.ckb;
+    let service = Service::new(&ckb.service_name);
+    let is_active = service.check_stoppable(stop_first)?;
+    if !yes {
+        let target = if peer_store {
+            "the peer store"
+        } else {
+            "all data"
+        };
+        let question = format!(
+            "This will remove {} of CKB in \"{}\" on host \"{}\".",
+            target,
+            ckb.root_dir.display(),
+            cfg.normal.host.name
+        );
+        if !prompt::confirm(&question, &cfg.normal.host.name)? {
+            return Err(Error::Exec("reset-data is not confirmed".to_owned()));
+        }
+    }
+    if backup_dir.is_some() || backup_upload {
+        backup_node_identity(cfg, backup_dir, backup_upload)?;
+    }
+    if is_active {
+        service.stop()?;
+    }
+    CkbCli::new(&ckb.bin_path, &ckb.root_dir).reset_data(peer_store)?;
+    if is_active {
+        service.start()?;
+    }
+    Ok(())
+}
+
+fn upgrade(
+    cfg: &Config,
+    binary: &Path,
+    sha256: Option<&str>,
+    no_checksum: bool,
+    timeout: StdDuration,
+    snapshot_dir: Option<&Path>,
+    yes: bool,
+) -> Result<()> {
+    let ckb = &cfg.normal.ckb;
+    if !no_checksum {
+        let expected = if let Some(sha256) = sha256 {
+            sha256.to_owned()
+        } else {
+            read_checksum_file(binary)?
+        };
+        let actual = hash::sha256_file(binary)?;
+        if actual != expected {
+            let msg = format!(
+                "checksum mismatch for '{}', expect {} but got {}",
+                binary.display(),
+                expected,
+                actual
+            );
+            return Err(Error::Exec(msg));
+        }
+    }
+    let tmp_dir = TempDir::new().map_err(|err| {
+        let msg = format!("failed to create tempdir since {}", err);
+        Error::Exec(msg)
+    })?;
+    let new_bin_path = if is_tarball(binary) {
+        archive::extract(binary, tmp_dir.path())?;
+        find_ckb_binary(tmp_dir.path())?
+    } else {
+        binary.to_owned()
+    };
+    let new_version = CkbCli::new(&new_bin_path, &ckb.root_dir).version()?;
+    let old_version = CkbCli::new(&ckb.bin_path, &ckb.root_dir).version()?;
+    println!("Upgrade from \"{}\" to \"{}\"", old_version, new_version);
+    // The database could only be checked by the new binary after the service is stopped, so
+    // the migration is confirmed before.
+    if !yes {
+        let question = format!(
+            "This will upgrade CKB from \"{}\" to \"{}\" on host \"{}\", and migrate the \
+            database in \"{}\" if required.",
+            old_version,
+            new_version,
+            cfg.normal.host.name,
+            ckb.data_dir.display()
+        );
+        if !prompt::confirm(&question, &cfg.normal.host.name)? {
+            return Err(Error::Exec("upgrade is not confirmed".to_owned()));
+        }
+    }
+    let service = Service::new(&ckb.service_name);
+    let is_active = service.is_active()?;
+    if is_active {
+        service.stop()?;
+    }
+    let prev_path = previous_bin_path(&ckb.bin_path);
+    let mut is_installed = false;
+    let result = (|| {
+        copy_file(&ckb.bin_path, &prev_path)?;
+        install_binary(&new_bin_path, &ckb.bin_path)?;
+        is_installed = true;
+        let cli = CkbCli::new(&ckb.bin_path, &ckb.root_dir);
+        if cli.migrate_check()? {
+            println!("The database requires a migration");
+            run_migration(cfg, &cli, snapshot_dir, true)?;
+        }
         Ok(())
+    })();
+    if let Err(err) = result {
+        // Put the old binary back, so the node is not left stopped or on an unchecked binary.
+        if is_installed {
+            rename(&prev_path, &ckb.bin_path)?;
+            println!("Failed to upgrade, restore \"{}\"", old_version);
+        }
+        if is_active {
+            service.start()?;
+        }
+        return Err(err);
+    }
+    let rollback_hint = |err: Error| {
+        println!(
+            "Failed to upgrade, use `l1 rollback` to restore \"{}\" if required",
+            old_version
+        );
+        err
+    };
+    service.start().map_err(rollback_hint)?;
+    wait_for_healthy(cfg, timeout).map_err(rollback_hint)?;
+    println!("CKB service is healthy with \"{}\"", new_version);
+    Ok(())
+}
+
+fn migrate(cfg: &Config, check: bool, snapshot_dir: Option<&Path>, yes: bool) -> Result<()> {
+    let ckb = &cfg.normal.ckb;
+    let cli = CkbCli::new(&ckb.bin_path, &ckb.root_dir);
+    if !cli.migrate_check()? {
+        println!("The database does not require a migration");
+        return Ok(());
+    }
+    println!("The database requires a migration");
+    if check {
+        return Ok(());
+    }
+    if Service::new(&ckb.service_name).is_active()? {
+        let msg = format!(
+            "service \"{}\" is running, stop it before migrating",
+            ckb.service_name
+        );
+        return Err(Error::Exec(msg));
+    }
+    run_migration(cfg, &cli, snapshot_dir, yes)
+}
+
+/// Migrates the database after it is confirmed, and archives it first if `snapshot_dir` is set.
+fn run_migration(cfg: &Config, cli: &CkbCli, snapshot_dir: Option<&Path>, yes: bool) -> Result<()> {
+    let ckb = &cfg.normal.ckb;
+    if !yes {
+        let question = format!(
+            "This will migrate the database of CKB in \"{}\" on host \"{}\".",
+            ckb.data_dir.display(),
+            cfg.normal.host.name
+        );
+        if !prompt::confirm(&question, &cfg.normal.host.name)? {
+            return Err(Error::Exec("migrate is not confirmed".to_owned()));
+        }
+    }
+    if let Some(snapshot_dir) = snapshot_dir {
+        let timestamp = Utc::now().format("%Y%m%d-%H%M%S");
+        let compression = Compression::default();
+        let target_name = format!(
+            "{}-db-{}.{}",
+            cfg.normal.host.name,
+            timestamp,
+            compression.extension()
+        );
+        let tgz_path = snapshot_dir.join(target_name);
+        let mut writer = ArchiveWriter::create(&tgz_path, compression, new_manifest(cfg, None))?;
+        writer.append_dir("db", &ckb.data_dir.join("db"))?;
+        writer.finish()?;
+        println!("Save {}", tgz_path.display());
+    }
+    cli.migrate()?;
+    println!("Migrate the database done");
+    Ok(())
+}
+
+fn rollback(cfg: &Config, timeout: StdDuration) -> Result<()> {
+    let ckb = &cfg.normal.ckb;
+    let prev_path = previous_bin_path(&ckb.bin_path);
+    if !prev_path.exists() {
+        let msg = format!("previous binary '{}' does not exist", prev_path.display());
+        return Err(Error::Exec(msg));
+    }
+    let version = CkbCli::new(&prev_path, &ckb.root_dir).version()?;
+    println!("Rollback to \"{}\"", version);
+    println!(
+        "If the database was migrated by the upgrade, \"{}\" may fail to open it, \
+        restore the database from the snapshot before the migration in that case",
+        version
+    );
+    let service = Service::new(&ckb.service_name);
+    if service.is_active()? {
+        service.stop()?;
+    }
+    rename(&prev_path, &ckb.bin_path)?;
+    service.start()?;
+    wait_for_healthy(cfg, timeout)?;
+    println!("CKB service is healthy with \"{}\"", version);
+    Ok(())
+}
+
+/// Reads the expected checksum from the sidecar file of `path`, as `sha256sum` writes it.
+fn read_checksum_file(path: &Path) -> Result<String> {
+    let mut checksum_path = path.as_os_str().to_owned();
+    checksum_path.push(".sha256");
+    let checksum_path = PathBuf::from(checksum_path);
+    let content = fs::read_to_string(&checksum_path).map_err(|err| {
+        let msg = format!(
+            "failed to read checksum file '{}' since {}, use `--sha256` or `--no-checksum`",
+            checksum_path.display(),
+            err
+        );
+        Error::Exec(msg)
+    })?;
+    content
+        .split_whitespace()
+        .next()
+        .map(str::to_lowercase)
+        .ok_or_else(|| {
+            let msg = format!("checksum file '{}' is empty", checksum_path.display());
+            Error::Exec(msg)
+        })
+}
+
+fn is_tarball(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.ends_with(".tar.gz") || name.ends_with(".tgz"))
+        .unwrap_or(false)
+}
+
+fn find_ckb_binary(dir: &Path) -> Result<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .find(|entry| entry.file_type().is_file() && entry.file_name() == "ckb")
+        .map(|entry| entry.into_path())
+        .ok_or_else(|| {
+            let msg = "failed to find the ckb binary in the tarball".to_owned();
+            Error::Exec(msg)
+        })
+}
+
+fn previous_bin_path(bin_path: &Path) -> PathBuf {
+    let mut path = bin_path.as_os_str().to_owned();
+    path.push(".prev");
+    PathBuf::from(path)
+}
+
+/// Copies the binary next to `bin_path` first, then renames it, so the swap is atomic.
+fn install_binary(src_path: &Path, bin_path: &Path) -> Result<()> {
+    let mut tmp_path = bin_path.as_os_str().to_owned();
+    tmp_path.push(".new");
+    let tmp_path = PathBuf::from(tmp_path);
+    copy_file(src_path, &tmp_path)?;
+    fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o755))
+        .and_then(|_| fs::rename(&tmp_path, bin_path))
+        .map_err(|err| {
+            let msg = format!(
+                "failed to install '{}' as '{}' since {}",
+                src_path.display(),
+                bin_path.display(),
+                err
+            );
+            Error::Exec(msg)
+        })
+}
+
+/// Waits until CKB service responds to JSON-RPC requests.
+fn wait_for_healthy(cfg: &Config, timeout: StdDuration) -> Result<()> {
+    let cli = RpcClient::new(&cfg.normal.ckb.rpc_url)?;
+    let started_at = Instant::now();
+    loop {
+        match cli.local_node_info() {
+            Ok(_) => return Ok(()),
+            Err(err) => log::debug!("CKB service is not ready since {}", err),
+        }
+        if started_at.elapsed() > timeout {
+            let msg = format!(
+                "CKB service is not healthy after {} seconds",
+                timeout.as_secs()
+            );
+            return Err(Error::Exec(msg));
+        }
+        thread::sleep(HEALTH_CHECK_INTERVAL);
+    }
+}
+
+/// Archives the peer store, the network secret key and `ckb.toml`.
+fn backup_node_identity(cfg: &Config, backup_dir: Option<&Path>, upload: bool) -> Result<()> {
+    let ckb = &cfg.normal.ckb;
+    let tmp_dir = TempDir::new().map_err(|err| {
+        let msg = format!("failed to create tempdir since {}", err);
+        Error::Exec(msg)
+    })?;
+    let compression = Compression::default();
+    let tgz_path = {
+        let timestamp = Utc::now().format("%Y%m%d-%H%M%S");
+        let target_name = format!(
+            "{}-reset-data-{}.{}",
+            cfg.normal.host.name,
+            timestamp,
+            compression.extension()
+        );
+        backup_dir
+            .unwrap_or_else(|| tmp_dir.path())
+            .join(target_name)
+    };
+    let mut writer = ArchiveWriter::create(&tgz_path, compression, new_manifest(cfg, None))?;
+    let peer_store_dir = ckb.data_dir.join("network").join("peer_store");
+    if peer_store_dir.exists() {
+        writer.append_dir("peer_store", &peer_store_dir)?;
+    }
+    for (src_path, name) in &[
+        (
+            ckb.data_dir.join("network").join("secret_key"),
+            "secret_key",
+        ),
+        (ckb.root_dir.join("ckb.toml"), "ckb.toml"),
+    ] {
+        if !src_path.exists() {
+            log::warn!("skip '{}' since it does not exist", src_path.display());
+            continue;
+        }
+        writer.append_file(name, src_path)?;
+    }
+    writer.finish()?;
+    if backup_dir.is_some() {
+        println!("Save {}", tgz_path.display());
+    }
+    if upload {
+        let url_ttl = Duration::days(storage::DEFAULT_URL_TTL_DAYS);
+        upload_archive(cfg, &tgz_path, backup_dir.is_some(), url_ttl, None)?;
+    }
+    Ok(())
+}
+
+/// Uploads the archive, it is moved out of the tempdir if the upload fails and it is not kept
+/// elsewhere, so it would not be lost.
+fn upload_archive(
+    cfg: &Config,
+    tgz_path: &Path,
+    is_kept: bool,
+    url_ttl: Duration,
+    tag: Option<&str>,
+) -> Result<()> {
+    // Only the uploaded copy is encrypted, and it is removed after uploaded.
+    let upload_path = if cfg.secret.encryption.is_some() {
+        let mut age_path = tgz_path.as_os_str().to_owned();
+        age_path.push(format!(".{}", encryption::EXTENSION));
+        PathBuf::from(age_path)
+    } else {
+        tgz_path.to_owned()
+    };
+    let result = cfg
+        .secret
+        .encryption
+        .as_ref()
+        .map_or(Ok(()), |encryption| {
+            encryption::encrypt_file(encryption, tgz_path, &upload_path)
+        })
+        .and_then(|_| {
+            let storage = storage::open(&cfg.secret.storage)?;
+            let host_name = &cfg.normal.host.name;
+            let key =
+                cfg.secret
+                    .key_template
+                    .render(storage.key_prefix(), host_name, &upload_path)?;
+            storage.check_url_ttl(url_ttl)?;
+            let metadata = {
+                let mut fields = BTreeMap::new();
+                fields.insert("host".to_owned(), host_name.to_owned());
+                if let Some(ckb_version) = ckb_version(cfg) {
+                    fields.insert("ckb-version".to_owned(), ckb_version);
+                }
+                if let Some(tag) = tag {
+                    fields.insert("tag".to_owned(), tag.to_owned());
+                }
+                let content_type = catalog::content_type(&upload_path).to_owned();
+                Metadata {
+                    content_type,
+                    fields,
+                }
+            };
+            let is_temporary = upload_path != tgz_path;
+            upload::upload(
+                storage.as_ref(),
+                &upload_path,
+                &key,
+                &metadata,
+                is_temporary,
+            )?;
+            Ok((storage, key))
+        });
+    match result {
+        Ok((storage, key)) => {
+            let url = storage.url(&key, url_ttl)?;
+            println!("Upload {} to {}", tgz_path.display(), url);
+            Ok(())
+        }
+        Err(err) => {
+            // The encrypted copy is kept with the archive, if its upload could be resumed.
+            let is_resumable = upload::is_resumable(&upload_path);
+            if upload_path != tgz_path && !is_resumable {
+                let _ = fs::remove_file(&upload_path);
+            }
+            let keep = |path: &Path| {
+                if is_kept {
+                    return Ok(path.to_owned());
+                }
+                let file_name = path.file_name().expect("file name of the archive");
+                let kept_path = env::temp_dir().join(file_name);
+                upload::rename(path, &kept_path)?;
+                Ok(kept_path)
+            };
+            let kept_path = keep(tgz_path)?;
+            println!(
+                "Failed to upload, the archive is kept in {}",
+                kept_path.display()
+            );
+            if is_resumable {
+                let kept_upload_path = if upload_path == tgz_path {
+                    kept_path
+                } else {
+                    keep(&upload_path)?
+                };
+                println!(
+                    "Resume the upload by `ckbdev backup resume {}`",
+                    kept_upload_path.display()
+                );
+            }
+            Err(err)
+        }
     }
 }
 
+/// Checks options of the upload before archiving, which takes a long time or stops CKB.
+fn check_output(cfg: &Config, output: &ArchiveOutput) -> Result<()> {
+    if output.upload {
+        storage::open(&cfg.secret.storage)?.check_url_ttl(output.url_ttl)?;
+    }
+    Ok(())
+}
+
+/// Keeps the archive in the output directory, and uploads it unless it is not required.
+fn deliver_archive(cfg: &Config, tgz_path: &Path, output: &ArchiveOutput) -> Result<()> {
+    if output.dir.is_some() {
+        println!("Save {}", tgz_path.display());
+    }
+    if output.upload {
+        upload_archive(
+            cfg,
+            tgz_path,
+            output.dir.is_some(),
+            output.url_ttl,
+            output.tag.as_deref(),
+        )?;
+    }
+    Ok(())
+}
+
+fn copy_file(src_path: &Path, dst_path: &Path) -> Result<()> {
+    fs::copy(src_path, dst_path).map_err(|err| {
+        let msg = format!(
+            "failed to copy '{}' to '{}' since {}",
+            src_path.display(),
+            dst_path.display(),
+            err
+        );
+        Error::Exec(msg)
+    })?;
+    Ok(())
+}
+
 impl CanExecute for L2Args {
     fn execute(&self, _cfg: &Config) -> Result<()> {
         Ok(())
@@ -90,152 +646,886 @@ impl CanExecute for L2Args {
 }
 
 impl CanExecute for BackupArgs {
+    fn execute(&self, cfg: &Config) -> Result<()> {
+        match self {
+            Self::Files(inner) => {
+                check_output(cfg, &inner.output)?;
+                inner.execute(cfg)
+            }
+            Self::Snapshot { stop_first, output } => {
+                check_output(cfg, output)?;
+                backup_snapshot(cfg, *stop_first, output)
+            }
+            Self::Diag(inner) => {
+                check_output(cfg, &inner.output)?;
+                inner.execute(cfg)
+            }
+            Self::List => backup_list(cfg),
+            Self::Check => backup_check(cfg),
+            Self::Fetch {
+                key,
+                output_dir,
+                identity,
+            } => backup_fetch(cfg, key, output_dir, identity.as_deref()),
+            Self::Share { key, url_ttl } => backup_share(cfg, key, *url_ttl),
+            Self::Resume { file, url_ttl } => backup_resume(cfg, file, *url_ttl),
+            Self::Prune {
+                keep_days,
+                keep_last,
+                dry_run,
+                yes,
+            } => backup_prune(cfg, *keep_days, *keep_last, *dry_run, *yes),
+        }
+    }
+}
+
+fn backup_list(cfg: &Config) -> Result<()> {
+    let storage = storage::open(&cfg.secret.storage)?;
+    let archives = catalog::list(
+        storage.as_ref(),
+        &cfg.secret.key_template,
+        &cfg.normal.host.name,
+    )?;
+    println!(
+        "{:<19}  {:<10} {:>10}  KEY",
+        "CREATED (UTC)", "KIND", "SIZE"
+    );
+    for archive in &archives {
+        println!(
+            "{:<19}  {:<10} {:>10}  {}",
+            archive.created.format("%Y-%m-%d %H:%M:%S"),
+            archive.kind,
+            format_size(archive.size),
+            archive.key
+        );
+    }
+    Ok(())
+}
+
+fn backup_check(cfg: &Config) -> Result<()> {
+    let storage = storage::open(&cfg.secret.storage)?;
+    let checks = storage.check();
+    let mut failed_count = 0;
+    for check in &checks {
+        match &check.result {
+            Ok(elapsed) => println!("OK   {} ({} ms)", check.target, elapsed.as_millis()),
+            Err(err) => {
+                failed_count += 1;
+                println!("FAIL {}: {}", check.target, err);
+            }
+        }
+    }
+    if failed_count > 0 {
+        let msg = format!("{} of {} checks failed", failed_count, checks.len());
+        return Err(Error::Exec(msg));
+    }
+    Ok(())
+}
+
+fn backup_fetch(cfg: &Config, key: &str, output_dir: &Path, identity: Option<&Path>) -> Result<()> {
+    let file_name = key
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| Error::Arg(format!("invalid key \"{}\"", key)))?;
+    let path = output_dir.join(file_name);
+    if path.exists() {
+        let msg = format!("'{}' already exists", path.display());
+        return Err(Error::Exec(msg));
+    }
+    let storage = storage::open(&cfg.secret.storage)?;
+    storage.download(key, &path)?;
+    println!("Fetch {} to {}", key, path.display());
+    let extension = format!(".{}", encryption::EXTENSION);
+    let archive_path = if let Some(decrypted_name) = file_name.strip_suffix(&extension) {
+        let secret = match (identity, &cfg.secret.encryption) {
+            (Some(identity), _) => Secret::IdentityFile(identity),
+            (None, Some(EncryptionSection::Passphrase(passphrase))) => {
+                Secret::Passphrase(passphrase)
+            }
+            _ => {
+                println!(
+                    "It is encrypted, decrypt it by `ckbdev decrypt` to verify it, or fetch it with `--identity`"
+                );
+                return Ok(());
+            }
+        };
+        let decrypted_path = output_dir.join(decrypted_name);
+        encryption::decrypt_file(&path, &decrypted_path, &secret)?;
+        println!("Decrypt {} to {}", path.display(), decrypted_path.display());
+        decrypted_path
+    } else {
+        path
+    };
+    match archive::verify(&archive_path)? {
+        Some(manifest) => println!(
+            "Verify {} files in {}",
+            manifest.files.len(),
+            archive_path.display()
+        ),
+        None => println!(
+            "No manifest in {}, it is not verified",
+            archive_path.display()
+        ),
+    }
+    Ok(())
+}
+
+fn backup_share(cfg: &Config, key: &str, url_ttl: Duration) -> Result<()> {
+    let storage = storage::open(&cfg.secret.storage)?;
+    if !storage.list(key)?.iter().any(|object| object.key == key) {
+        let msg = format!("\"{}\" is not found in the storage", key);
+        return Err(Error::Exec(msg));
+    }
+    println!("{}", storage.url(key, url_ttl)?);
+    Ok(())
+}
+
+fn backup_resume(cfg: &Config, file_path: &Path, url_ttl: Duration) -> Result<()> {
+    let storage = storage::open(&cfg.secret.storage)?;
+    storage.check_url_ttl(url_ttl)?;
+    let key = upload::resume(storage.as_ref(), file_path)?;
+    println!(
+        "Upload {} to {}",
+        file_path.display(),
+        storage.url(&key, url_ttl)?
+    );
+    Ok(())
+}
+
+fn backup_prune(
+    cfg: &Config,
+    keep_days: Option<u32>,
+    keep_last: Option<usize>,
+    dry_run: bool,
+    yes: bool,
+) -> Result<()> {
+    let host_name = &cfg.normal.host.name;
+    let storage = storage::open(&cfg.secret.storage)?;
+    let archives = catalog::list(storage.as_ref(), &cfg.secret.key_template, host_name)?;
+    let expired = catalog::expired(&archives, keep_days, keep_last, Utc::now());
+    if expired.is_empty() {
+        println!("No archives are expired");
+        return Ok(());
+    }
+    for archive in &expired {
+        println!("Expired {}", archive.key);
+    }
+    if dry_run {
+        return Ok(());
+    }
+    if !yes {
+        let question = format!(
+            "Delete {} of {} archives of {} from the storage?",
+            expired.len(),
+            archives.len(),
+            host_name
+        );
+        if !prompt::confirm(&question, host_name)? {
+            return Err(Error::Exec("prune is not confirmed".to_owned()));
+        }
+    }
+    for archive in expired {
+        storage.delete(&archive.key)?;
+        println!("Delete {}", archive.key);
+    }
+    Ok(())
+}
+
+/// Formats a size in bytes with a binary unit, such as "1.5 GiB".
+fn format_size(size: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next_unit;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+fn backup_snapshot(cfg: &Config, stop_first: bool, output: &ArchiveOutput) -> Result<()> {
+    let ckb = &cfg.normal.ckb;
+    let service = Service::new(&ckb.service_name);
+    let is_active = service.check_stoppable(stop_first)?;
+    if is_active {
+        service.stop()?;
+    }
+    let result = (|| {
+        let tmp_dir = TempDir::new().map_err(|err| {
+            let msg = format!("failed to create tempdir since {}", err);
+            Error::Exec(msg)
+        })?;
+        let archive_name = {
+            let timestamp = Utc::now().format("%Y%m%d-%H%M%S");
+            format!("{}-snapshot-{}", cfg.normal.host.name, timestamp)
+        };
+        let tgz_path = output.archive_path(tmp_dir.path(), &archive_name);
+        let mut writer =
+            ArchiveWriter::create(&tgz_path, output.compression, new_manifest(cfg, None))?;
+        writer.append_dir("db", &ckb.data_dir.join("db"))?;
+        writer.finish()?;
+        Ok((tmp_dir, tgz_path))
+    })();
+    if is_active {
+        service.start()?;
+    }
+    let (tmp_dir, tgz_path) = result?;
+    deliver_archive(cfg, &tgz_path, output)?;
+    drop(tmp_dir);
+    Ok(())
+}
+
+impl CanExecuteWithoutConfig for DecryptArgs {
+    fn execute_without_config(&self) -> Result<()> {
+        let secret = match (&self.identity, &self.passphrase_file) {
+            (Some(path), _) => Secret::IdentityFile(path),
+            (None, Some(path)) => Secret::PassphraseFile(path),
+            (None, None) => Secret::Prompt,
+        };
+        encryption::decrypt_file(&self.archive, &self.output, &secret)?;
+        println!(
+            "Decrypt {} to {}",
+            self.archive.display(),
+            self.output.display()
+        );
+        Ok(())
+    }
+}
+
+impl CanExecute for RestoreArgs {
+    fn execute(&self, cfg: &Config) -> Result<()> {
+        let data_dir = &cfg.normal.ckb.data_dir;
+        match self {
+            Self::Snapshot {
+                archive,
+                stop_first,
+                yes,
+            } => {
+                let target = RestoreTarget {
+                    name: "database",
+                    entry: "db",
+                    dir: data_dir.join("db"),
+                    require_manifest: true,
+                };
+                restore(cfg, &target, archive, *stop_first, *yes)
+            }
+            Self::PeerStore {
+                archive,
+                stop_first,
+                yes,
+            } => {
+                let target = RestoreTarget {
+                    name: "peer store",
+                    entry: "peer_store",
+                    dir: data_dir.join("network").join("peer_store"),
+                    require_manifest: false,
+                };
+                restore(cfg, &target, archive, *stop_first, *yes)
+            }
+        }
+    }
+}
+
+/// A directory which could be replaced by the same named entry in an archive.
+struct RestoreTarget {
+    name: &'static str,
+    entry: &'static str,
+    dir: PathBuf,
+    require_manifest: bool,
+}
+
+fn restore(
+    cfg: &Config,
+    target: &RestoreTarget,
+    archive: &ArchiveSource,
+    stop_first: bool,
+    yes: bool,
+) -> Result<()> {
+    let ckb = &cfg.normal.ckb;
+    let service = Service::new(&ckb.service_name);
+    let is_active = service.check_stoppable(stop_first)?;
+    let parent_dir = target.dir.parent().expect("restore target has a parent");
+    create_dir(parent_dir)?;
+    // Unpack next to the target, so it could be moved in by renaming.
+    let staging_dir = tempfile::Builder::new()
+        .prefix(".restore-")
+        .tempdir_in(parent_dir)
+        .map_err(|err| {
+            let msg = format!(
+                "failed to create tempdir in '{}' since {}",
+                parent_dir.display(),
+                err
+            );
+            Error::Exec(msg)
+        })?;
+    let archive_path = fetch_archive(archive, staging_dir.path())?;
+    let unpack_dir = staging_dir.path().join("unpack");
+    create_dir(&unpack_dir)?;
+    archive::extract(&archive_path, &unpack_dir)?;
+    let unpacked_path = unpack_dir.join(target.entry);
+    let is_empty = fs::read_dir(&unpacked_path)
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(true);
+    if is_empty {
+        let msg = format!(
+            "invalid archive since the {} is not found in \"{}\"",
+            target.name, target.entry
+        );
+        return Err(Error::Exec(msg));
+    }
+    let manifest_path = unpack_dir.join(MANIFEST_FILE_NAME);
+    if target.require_manifest || manifest_path.exists() {
+        Manifest::load_from_file(&manifest_path)?.verify(&unpack_dir)?;
+    }
+    if !yes {
+        let question = format!(
+            "This will replace the {} of CKB in \"{}\" on host \"{}\".",
+            target.name,
+            target.dir.display(),
+            cfg.normal.host.name
+        );
+        if !prompt::confirm(&question, &cfg.normal.host.name)? {
+            return Err(Error::Exec("restore is not confirmed".to_owned()));
+        }
+    }
+    if is_active {
+        service.stop()?;
+    }
+    let result = (|| {
+        if target.dir.exists() {
+            let timestamp = Utc::now().format("%Y%m%d-%H%M%S");
+            let mut old_dir = target.dir.as_os_str().to_owned();
+            old_dir.push(format!(".old-{}", timestamp));
+            let old_dir = PathBuf::from(old_dir);
+            rename(&target.dir, &old_dir)?;
+            if let Err(err) = rename(&unpacked_path, &target.dir) {
+                rename(&old_dir, &target.dir)?;
+                return Err(err);
+            }
+            println!("Keep the old {} in {}", target.name, old_dir.display());
+            Ok(())
+        } else {
+            rename(&unpacked_path, &target.dir)
+        }
+    })();
+    if is_active {
+        service.start()?;
+    }
+    result?;
+    println!(
+        "Restore the {} from {}",
+        target.name,
+        archive_path.display()
+    );
+    Ok(())
+}
+
+/// Returns the local path of the archive, downloads it into `dir` if it is remote.
+fn fetch_archive(archive: &ArchiveSource, dir: &Path) -> Result<PathBuf> {
+    match archive {
+        ArchiveSource::Local(path) => Ok(path.to_owned()),
+        ArchiveSource::Remote(url) => {
+            let file_name = url
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .filter(|name| !name.is_empty())
+                .unwrap_or("archive.tar.gz");
+            let path = dir.join(file_name);
+            download::download(url, &path)?;
+            Ok(path)
+        }
+    }
+}
+
+fn create_file(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|err| {
+            let msg = format!("failed to create '{}' since {}", path.display(), err);
+            Error::Exec(msg)
+        })
+}
+
+fn create_dir(path: &Path) -> Result<()> {
+    fs::create_dir_all(path).map_err(|err| {
+        let msg = format!("failed to create '{}' since {}", path.display(), err);
+        Error::Exec(msg)
+    })
+}
+
+pub(crate) fn rename(src_path: &Path, dst_path: &Path) -> Result<()> {
+    fs::rename(src_path, dst_path).map_err(|err| {
+        let msg = format!(
+            "failed to move '{}' to '{}' since {}",
+            src_path.display(),
+            dst_path.display(),
+            err
+        );
+        Error::Exec(msg)
+    })
+}
+
+impl CanExecute for BackupFilesArgs {
     fn execute(&self, cfg: &Config) -> Result<()> {
         let ckb_data_dir = &cfg.normal.ckb.data_dir;
         let tmp_dir = TempDir::new().map_err(|err| {
             let msg = format!("failed to create tempdir since {}", err);
             Error::Exec(msg)
         })?;
-        let tgz_path = {
+        let archive_name = {
             let timestamp = Utc::now().format("%Y%m%d-%H%M%S");
-            let target_name = format!("{}-{}.tar.gz", cfg.normal.host.name, timestamp);
-            tmp_dir.path().join(target_name)
+            format!("{}-{}", cfg.normal.host.name, timestamp)
         };
-        let mut command = Command::new("tar");
-        command.current_dir(tmp_dir.path()).args(&[
-            "-czvf",
-            tgz_path.as_path().to_str().expect("tgz_path.to_str()"),
-        ]);
+        let tgz_path = self.output.archive_path(tmp_dir.path(), &archive_name);
+        let redactor = self
+            .redact
+            .as_ref()
+            .map(|_| Redactor::new(&cfg.secret.secrets()));
+        let manifest = if self.peer_store {
+            new_manifest(cfg, None)
+        } else {
+            new_manifest(cfg, Some(&self.time_range))
+        };
+        let mut writer = ArchiveWriter::create(&tgz_path, self.output.compression, manifest)?;
         if self.peer_store {
-            let src_path = ckb_data_dir.join("network").join("peer_store");
-            let mut options = dir::CopyOptions::new();
-            options.copy_inside = true;
-            dir::copy(&src_path, &tmp_dir, &options).map_err(|err| {
-                let msg = format!(
-                    "failed to copy '{}' into '{}' since {}",
-                    src_path.display(),
-                    tmp_dir.path().display(),
-                    err
-                );
-                Error::Exec(msg)
-            })?;
-            command.arg("peer_store");
+            let peer_store_dir = ckb_data_dir.join("network").join("peer_store");
+            writer.append_dir("peer_store", &peer_store_dir)?;
         } else {
-            let logs_dir = ckb_data_dir.join("logs");
-            let dst_path = tmp_dir.path().join("ckb.log");
-            let mut write_file = OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&dst_path)
-                .expect("open log file to write");
-            let re = Regex::new(LOG_TIMESTAMP_REGEX).expect("compile regex");
-            'read_logfile: for entry in WalkDir::new(logs_dir).into_iter().filter_map(|e| e.ok()) {
-                if entry.file_type().is_dir() {
-                    continue;
+            append_logs(
+                &mut writer,
+                cfg,
+                &self.time_range,
+                &self.filter,
+                self.with_jsonl,
+                redactor.as_ref(),
+            )?;
+        }
+        if self.with_journal {
+            let raw_path = tmp_dir.path().join("journal.log.raw");
+            let write_file = create_file(&raw_path)?;
+            Service::new(&cfg.normal.ckb.service_name)
+                .journal(&self.time_range, write_file.into())?;
+            if let Some(ref redactor) = redactor {
+                let dst_path = tmp_dir.path().join("journal.log");
+                redactor.redact_file(&raw_path, &dst_path)?;
+                writer.append_file("journal.log", &dst_path)?;
+            } else {
+                writer.append_file("journal.log", &raw_path)?;
+            }
+        }
+        writer.finish()?;
+        if let (Some(redactor), Some(mapping_dir)) = (&redactor, &self.redact) {
+            save_redaction_mapping(redactor, mapping_dir, &archive_name)?;
+        }
+        deliver_archive(cfg, &tgz_path, &self.output)?;
+        drop(tmp_dir);
+        Ok(())
+    }
+}
+
+impl CanExecute for BackupDiagArgs {
+    fn execute(&self, cfg: &Config) -> Result<()> {
+        let ckb = &cfg.normal.ckb;
+        let tmp_dir = TempDir::new().map_err(|err| {
+            let msg = format!("failed to create tempdir since {}", err);
+            Error::Exec(msg)
+        })?;
+        let archive_name = {
+            let timestamp = Utc::now().format("%Y%m%d-%H%M%S");
+            format!("{}-diag-{}", cfg.normal.host.name, timestamp)
+        };
+        let tgz_path = self.output.archive_path(tmp_dir.path(), &archive_name);
+        let manifest = new_manifest(cfg, Some(&self.time_range));
+        let mut writer = ArchiveWriter::create(&tgz_path, self.output.compression, manifest)?;
+        // The config is always redacted, other files are redacted only if required.
+        let redactor = Redactor::new(&cfg.secret.secrets());
+        let redact = |content: String| {
+            if self.redact.is_some() {
+                redactor.redact(&content)
+            } else {
+                content
+            }
+        };
+        append_logs(
+            &mut writer,
+            cfg,
+            &self.time_range,
+            &self.filter,
+            false,
+            self.redact.as_ref().map(|_| &redactor),
+        )?;
+
+        let cli = RpcClient::new(&ckb.rpc_url)?;
+        let dumps = vec![
+            ("get_peers", cli.get_peers().and_then(to_json_value)),
+            (
+                "local_node_info",
+                cli.local_node_info().and_then(to_json_value),
+            ),
+            ("sync_state", cli.sync_state().and_then(to_json_value)),
+            ("tx_pool_info", cli.tx_pool_info().and_then(to_json_value)),
+            (
+                "get_blockchain_info",
+                cli.get_blockchain_info().and_then(to_json_value),
+            ),
+        ];
+        for (method, result) in dumps {
+            match result {
+                Ok(value) => {
+                    let content = to_string_pretty(&value).expect("serde_json::to_string(..)");
+                    let name = format!("rpc/{}.json", method);
+                    writer.append_bytes(&name, redact(content).as_bytes())?;
                 }
-                let is_log = entry
-                    .file_name()
-                    .to_str()
-                    .map(|s| s.ends_with(".log"))
-                    .unwrap_or(false);
-                if !is_log {
-                    continue;
+                Err(err) => {
+                    // The node may be down, which is the reason for diagnosis.
+                    log::warn!("failed to dump \"{}\" since {}", method, err);
+                    let name = format!("rpc/{}.error", method);
+                    writer.append_bytes(&name, err.to_string().as_bytes())?;
                 }
-                log::trace!("read log file '{}'", entry.path().display());
-                let mut read_file = OpenOptions::new()
-                    .read(true)
-                    .open(entry.path())
-                    .expect("open log file to read");
-                let mut check_tail = false;
-                let mut skip_at_head = 0;
-                for line in BufReader::new(&read_file).lines() {
-                    let line_str = line.expect("read line");
-                    if let Some(mat) = re.find(&line_str) {
-                        let line_ts_str = &line_str.split_at(mat.end() - 1).0;
-                        let line_ts = DateTime::parse_from_str(line_ts_str, LOG_TIMESTAMP_FORMAT)
-                            .unwrap_or_else(|err| panic!("regex should be right but {}", err));
-                        if line_ts > self.logs_around.1 {
-                            log::trace!(
-                                "skip log file '{}' since after time scope",
-                                entry.path().display(),
-                            );
-                            continue 'read_logfile;
-                        } else if line_ts < self.logs_around.0 {
-                            check_tail = true;
-                        }
+            }
+        }
+
+        match Service::new(&ckb.service_name).status() {
+            Ok(status) => {
+                let content = to_string_pretty(&status).expect("serde_json::to_string(..)");
+                writer.append_bytes("systemctl.json", content.as_bytes())?;
+            }
+            Err(err) => {
+                log::warn!("failed to get the service status since {}", err);
+                writer.append_bytes("systemctl.error", err.to_string().as_bytes())?;
+            }
+        }
+
+        let config_path = ckb.root_dir.join("ckb.toml");
+        match fs::read_to_string(&config_path) {
+            Ok(config) => {
+                writer.append_bytes("ckb.toml", redactor.redact(&config).as_bytes())?;
+            }
+            Err(err) => {
+                let msg = format!("failed to read '{}' since {}", config_path.display(), err);
+                log::warn!("{}", msg);
+                writer.append_bytes("ckb.toml.error", msg.as_bytes())?;
+            }
+        }
+
+        let version = CkbCli::new(&ckb.bin_path, &ckb.root_dir)
+            .version()
+            .unwrap_or_else(|err| err.to_string());
+        writer.append_bytes("ckb-version.txt", version.as_bytes())?;
+
+        let facts = host::facts(&ckb.data_dir);
+        let content = to_string_pretty(&facts).expect("serde_json::to_string(..)");
+        writer.append_bytes("host.json", content.as_bytes())?;
+
+        writer.finish()?;
+        if let Some(ref mapping_dir) = self.redact {
+            save_redaction_mapping(&redactor, mapping_dir, &archive_name)?;
+        }
+        deliver_archive(cfg, &tgz_path, &self.output)?;
+        drop(tmp_dir);
+        Ok(())
+    }
+}
+
+/// Creates the manifest of an archive of this host, with the time range of its logs.
+fn new_manifest(cfg: &Config, time_range: Option<&TimeRange>) -> Manifest {
+    let ckb_version = ckb_version(cfg);
+    let time_range = time_range.map(|time_range| TimeWindow {
+        from: time_range.from.to_rfc3339(),
+        to: time_range.to.to_rfc3339(),
+    });
+    Manifest {
+        host: Some(cfg.normal.host.name.clone()),
+        ckb_version,
+        time_range,
+        files: Vec::new(),
+    }
+}
+
+/// The version of CKB, it is unknown if CKB fails to run.
+fn ckb_version(cfg: &Config) -> Option<String> {
+    let ckb = &cfg.normal.ckb;
+    CkbCli::new(&ckb.bin_path, &ckb.root_dir)
+        .version()
+        .map_err(|err| log::warn!("failed to get the version of CKB since {}", err))
+        .ok()
+}
+
+/// Streams records of CKB logs in the time range into "ckb.log" of the archive, and also
+/// "ckb.log.jsonl" if `with_jsonl`.
+fn append_logs(
+    writer: &mut ArchiveWriter,
+    cfg: &Config,
+    time_range: &TimeRange,
+    filter: &RecordFilter,
+    with_jsonl: bool,
+    redactor: Option<&Redactor>,
+) -> Result<()> {
+    let logs_dir = cfg.normal.ckb.data_dir.join("logs");
+    let mut records = ckb_log::open_dir_range(&logs_dir, time_range).map_err(|err| {
+        let msg = format!("failed to open logs since {}", err);
+        Error::Exec(msg)
+    })?;
+    // Logs are read only once, for both files.
+    let mut log_spool = writer.spool()?;
+    let mut jsonl_spool = if with_jsonl {
+        Some(writer.spool()?)
+    } else {
+        None
+    };
+    let write_error = |err: io::Error| {
+        let msg = format!("failed to write logs into a spool file since {}", err);
+        Error::Exec(msg)
+    };
+    while let Some(record) = records.next() {
+        let record = record.map_err(|err| {
+            let msg = format!("failed to read logs since {}", err);
+            Error::Exec(msg)
+        })?;
+        if !filter.matches(&record) {
+            continue;
+        }
+        let record = match redactor {
+            Some(redactor) => redactor.redact_record(&record),
+            None => record,
+        };
+        if let Some(jsonl_spool) = jsonl_spool.as_mut() {
+            let json = record_to_json(&records, &record);
+            writeln!(jsonl_spool, "{}", json).map_err(write_error)?;
+        }
+        writeln!(log_spool, "{}", record.raw).map_err(write_error)?;
+    }
+    writer.append_spooled("ckb.log", log_spool)?;
+    if let Some(jsonl_spool) = jsonl_spool {
+        writer.append_spooled("ckb.log.jsonl", jsonl_spool)?;
+    }
+    Ok(())
+}
+
+fn save_redaction_mapping(redactor: &Redactor, dir: &Path, archive_name: &str) -> Result<()> {
+    let path = dir.join(format!("{}.redact.json", archive_name));
+    redactor.save_mapping(&path)?;
+    println!("Save the redaction mapping into {}", path.display());
+    Ok(())
+}
+
+fn to_json_value<T: serde::Serialize>(value: T) -> Result<serde_json::Value> {
+    serde_json::to_value(value).map_err(|err| {
+        let msg = format!("failed to serialize the rpc return since {}", err);
+        Error::Rpc(msg)
+    })
+}
+
+impl CanExecute for LogsArgs {
+    fn execute(&self, cfg: &Config) -> Result<()> {
+        match self {
+            Self::Show { time_range, filter } => {
+                let logs_dir = cfg.normal.ckb.data_dir.join("logs");
+                let records = ckb_log::open_dir_range(&logs_dir, time_range).map_err(|err| {
+                    let msg = format!("failed to open logs since {}", err);
+                    Error::Exec(msg)
+                })?;
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                for record in records {
+                    let record = record.map_err(|err| Error::Exec(err.to_string()))?;
+                    if !filter.matches(&record) {
+                        continue;
+                    }
+                    if writeln!(stdout, "{}", record.raw).is_err() {
+                        // The reader of the pipe is closed.
                         break;
                     }
-                    skip_at_head += 1
                 }
-                if check_tail {
-                    read_file.seek(SeekFrom::Start(0)).expect("seek to start");
-                    let lines_count = BufReader::new(&read_file).lines().count();
-                    read_file.seek(SeekFrom::Start(0)).expect("seek to start");
-                    let last_lines: Vec<String> = if lines_count > LOG_MIN_TAIL_CHECK {
-                        BufReader::new(&read_file)
-                            .lines()
-                            .skip(lines_count - LOG_MIN_TAIL_CHECK)
-                            .map(|line| line.expect("read line"))
-                            .collect()
-                    } else {
-                        BufReader::new(&read_file)
-                            .lines()
-                            .map(|line| line.expect("read line"))
-                            .collect()
-                    };
-                    for line_str in last_lines.into_iter().rev() {
-                        if let Some(mat) = re.find(&line_str) {
-                            let line_ts_str = &line_str.split_at(mat.end() - 1).0;
-                            let line_ts =
-                                DateTime::parse_from_str(line_ts_str, LOG_TIMESTAMP_FORMAT)
-                                    .unwrap_or_else(|err| {
-                                        panic!("regex should be right but {}", err)
-                                    });
-                            if line_ts < self.logs_around.0 {
-                                log::trace!(
-                                    "skip log file '{}' since before time scope",
-                                    entry.path().display()
-                                );
-                                continue 'read_logfile;
-                            }
-                            break;
-                        }
+                Ok(())
+            }
+            Self::Tail {
+                lines,
+                follow,
+                color,
+                filter,
+            } => {
+                let is_colored = match color {
+                    ColorMode::Auto => atty::is(atty::Stream::Stdout),
+                    ColorMode::Always => true,
+                    ColorMode::Never => false,
+                };
+                tail_logs(cfg, *lines, *follow, is_colored, filter)
+            }
+            Self::Export {
+                time_range,
+                format,
+                output,
+                filter,
+            } => {
+                let logs_dir = cfg.normal.ckb.data_dir.join("logs");
+                let mut records =
+                    ckb_log::open_dir_range(&logs_dir, time_range).map_err(|err| {
+                        let msg = format!("failed to open logs since {}", err);
+                        Error::Exec(msg)
+                    })?;
+                let (mut writer, name): (Box<dyn Write>, _) = if let Some(path) = output {
+                    let writer = BufWriter::new(create_file(path)?);
+                    (Box::new(writer), path.display().to_string())
+                } else {
+                    (Box::new(io::stdout()), "stdout".to_owned())
+                };
+                while let Some(record) = records.next() {
+                    let record = record.map_err(|err| Error::Exec(err.to_string()))?;
+                    if !filter.matches(&record) {
+                        continue;
                     }
+                    let line = match format {
+                        ExportFormat::JsonLines => record_to_json(&records, &record).to_string(),
+                    };
+                    writeln!(writer, "{}", line).map_err(|err| {
+                        let msg = format!("failed to write {} since {}", name, err);
+                        Error::Exec(msg)
+                    })?;
                 }
-                read_file.seek(SeekFrom::Start(0)).expect("seek to start");
-                for line in BufReader::new(&read_file).lines().skip(skip_at_head) {
-                    let line_str = line.expect("read line");
-                    if let Some(mat) = re.find(&line_str) {
-                        let line_ts_str = &line_str.split_at(mat.end() - 1).0;
-                        let line_ts = DateTime::parse_from_str(line_ts_str, LOG_TIMESTAMP_FORMAT)
-                            .unwrap_or_else(|err| panic!("regex should be right but {}", err));
-                        if line_ts < self.logs_around.0 {
-                            continue;
-                        } else if line_ts > self.logs_around.1 {
-                            break;
-                        }
-                    }
-                    write_file
-                        .write_all(line_str.as_bytes())
-                        .expect("write into log file");
-                    write_file
-                        .write_all("\n".as_bytes())
-                        .expect("write into log file");
+                writer.flush().map_err(|err| {
+                    let msg = format!("failed to write {} since {}", name, err);
+                    Error::Exec(msg)
+                })
+            }
+            Self::Summarize { time_range, top } => {
+                let logs_dir = cfg.normal.ckb.data_dir.join("logs");
+                let records = ckb_log::open_dir_range(&logs_dir, time_range).map_err(|err| {
+                    let msg = format!("failed to open logs since {}", err);
+                    Error::Exec(msg)
+                })?;
+                let mut summary = Summary::new(*top);
+                for record in records {
+                    let record = record.map_err(|err| Error::Exec(err.to_string()))?;
+                    summary.add(&record);
                 }
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                summary
+                    .write_report(&mut stdout, time_range)
+                    .map_err(|err| Error::Exec(format!("failed to print the report since {}", err)))
+            }
+            Self::Journal { time_range } => {
+                Service::new(&cfg.normal.ckb.service_name).journal(time_range, Stdio::inherit())
             }
-            command.arg("ckb.log");
         }
-        command.stdout(Stdio::inherit()).output().map_err(|err| {
-            let msg = format!("failed to execute `{:?}` since {}", command, err);
-            Error::Exec(msg)
-        })?;
-        let url = qiniu::upload(&cfg.secret.qiniu, tgz_path.as_path())?;
-        println!("Upload {} to {}", tgz_path.as_path().display(), url);
-        drop(tmp_dir);
+    }
+}
+
+/// Converts the last record of the reader into a JSON object.
+fn record_to_json(records: &MergedReader, record: &Record) -> serde_json::Value {
+    let file = records
+        .last_file()
+        .and_then(Path::file_name)
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    record.to_json(file)
+}
+
+fn tail_logs(
+    cfg: &Config,
+    lines: usize,
+    follow: bool,
+    is_colored: bool,
+    filter: &RecordFilter,
+) -> Result<()> {
+    let logs_dir = cfg.normal.ckb.data_dir.join("logs");
+    let path = ckb_log::find_active_log(&logs_dir).ok_or_else(|| {
+        let msg = format!("failed to find any log file in '{}'", logs_dir.display());
+        Error::Exec(msg)
+    })?;
+    let (records, offset) = ckb_log::read_tail(&path, lines, filter).map_err(|err| {
+        let msg = format!("failed to read '{}' since {}", path.display(), err);
+        Error::Exec(msg)
+    })?;
+    let print = |record: &Record| {
+        if !filter.matches(record) {
+            return Ok(());
+        }
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if is_colored {
+            print_colored(&mut stdout, record)
+        } else {
+            writeln!(stdout, "{}", record.raw)
+        }
+        .map_err(|err| Error::Exec(format!("failed to print since {}", err)))
+    };
+    for record in &records {
+        print(record)?;
+    }
+    if !follow {
+        return Ok(());
+    }
+    let open = |offset: u64| {
+        File::open(&path)
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(offset))?;
+                let ino = file.metadata()?.ino();
+                Ok((RecordReader::following(BufReader::new(file)), ino))
+            })
+            .map_err(|err| {
+                let msg = format!("failed to open '{}' since {}", path.display(), err);
+                Error::Exec(msg)
+            })
+    };
+    let print_all = |reader: &mut RecordReader<BufReader<File>>| {
+        for record in reader {
+            let record = record.map_err(|err| {
+                let msg = format!("failed to read '{}' since {}", path.display(), err);
+                Error::Exec(msg)
+            })?;
+            print(&record)?;
+        }
         Ok(())
+    };
+    // A record is printed once the next record starts, since its continuation lines could be
+    // written later.
+    let (mut reader, mut ino) = open(offset)?;
+    loop {
+        print_all(&mut reader)?;
+        let position = reader.get_mut().get_mut().stream_position().unwrap_or(0);
+        let is_rotated = fs::metadata(&path)
+            .map(|metadata| metadata.ino() != ino || metadata.len() < position)
+            .unwrap_or(false);
+        if is_rotated {
+            log::debug!("reopen '{}' since it is rotated", path.display());
+            // Records which are written before the rotation are still in the old file.
+            reader.stop_following();
+            print_all(&mut reader)?;
+            let (new_reader, new_ino) = open(0)?;
+            reader = new_reader;
+            ino = new_ino;
+            continue;
+        }
+        thread::sleep(FOLLOW_INTERVAL);
     }
 }
 
+fn print_colored<W: Write>(writer: &mut W, record: &Record) -> io::Result<()> {
+    let color = match record.level {
+        Level::Error => "\x1b[31m",
+        Level::Warn => "\x1b[33m",
+        Level::Info => "\x1b[32m",
+        Level::Debug => "\x1b[34m",
+        Level::Trace => "\x1b[2m",
+    };
+    let (head, rest) = match record.raw.find('\n') {
+        Some(index) => record.raw.split_at(index),
+        None => (record.raw.as_str(), ""),
+    };
+    writeln!(writer, "{}{}\x1b[0m{}", color, head, rest)
+}
+
 impl CanExecute for RpcArgs {
     fn execute(&self, cfg: &Config) -> Result<()> {
         let cli = RpcClient::new(&cfg.normal.ckb.rpc_url)?;
@@ -0,0 +1,54 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use sha2::{Digest as _, Sha256};
+
+use crate::error::{Error, Result};
+
+/// Calculates the SHA-256 of a file and returns it as a lowercase hex string.
+pub(crate) fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).map_err(|err| {
+        let msg = format!("failed to open '{}' since {}", path.display(), err);
+        Error::Exec(msg)
+    })?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|err| {
+        let msg = format!("failed to read '{}' since {}", path.display(), err);
+        Error::Exec(msg)
+    })?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Calculates the size and the SHA-256 of the data while reading it.
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R> HashingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Returns the size and the SHA-256 as a lowercase hex string of the data which was read.
+    pub(crate) fn finish(self) -> (u64, String) {
+        (self.size, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.hasher.update(&buf[..len]);
+        self.size += len as u64;
+        Ok(len)
+    }
+}
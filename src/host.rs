@@ -0,0 +1,94 @@
+use std::{fs, path::Path, process::Command};
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    command,
+    error::{Error, Result},
+};
+
+/// Collects facts of the host, such as disk, memory, uptime and load.
+///
+/// A fact which fails to be collected is an error message, so the others are still useful.
+pub(crate) fn facts(data_dir: &Path) -> Value {
+    let to_value = |result: Result<Value>| result.unwrap_or_else(|err| json!(err.to_string()));
+    json!({
+        "disk": to_value(disk_usage(data_dir)),
+        "memory": to_value(memory()),
+        "uptime": to_value(uptime()),
+        "load": to_value(load()),
+    })
+}
+
+/// Usage of the file system which has the directory, in KiB.
+#[allow(unknown_lints, clippy::needless_borrows_for_generic_args)]
+fn disk_usage(dir: &Path) -> Result<Value> {
+    let output = command::output(Command::new("df").args(&["-P", "-k"]).arg(dir))?;
+    let line = output
+        .lines()
+        .nth(1)
+        .ok_or_else(|| Error::Exec(format!("unknown output of df: {}", output)))?;
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    if fields.len() < 6 {
+        return Err(Error::Exec(format!("unknown output of df: {}", output)));
+    }
+    let parse = |s: &str| {
+        s.parse::<u64>()
+            .map_err(|err| Error::Exec(format!("failed to parse \"{}\" since {}", s, err)))
+    };
+    Ok(json!({
+        "filesystem": fields[0],
+        "total_kib": parse(fields[1])?,
+        "used_kib": parse(fields[2])?,
+        "available_kib": parse(fields[3])?,
+        "capacity": fields[4],
+        "mounted_on": fields[5..].join(" "),
+    }))
+}
+
+/// All fields of "/proc/meminfo", in KiB.
+fn memory() -> Result<Value> {
+    let content = read_proc_file("/proc/meminfo")?;
+    let mut memory = Map::new();
+    for line in content.lines() {
+        let mut parts = line.splitn(2, ':');
+        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            let value = value.trim().trim_end_matches(" kB");
+            if let Ok(value) = value.parse::<u64>() {
+                memory.insert(key.to_owned(), json!(value));
+            }
+        }
+    }
+    Ok(Value::Object(memory))
+}
+
+fn uptime() -> Result<Value> {
+    let content = read_proc_file("/proc/uptime")?;
+    let seconds = content
+        .split_whitespace()
+        .next()
+        .and_then(|s| s.parse::<f64>().ok())
+        .ok_or_else(|| Error::Exec(format!("unknown content of /proc/uptime: {}", content)))?;
+    Ok(json!({ "seconds": seconds }))
+}
+
+fn load() -> Result<Value> {
+    let content = read_proc_file("/proc/loadavg")?;
+    let averages = content
+        .split_whitespace()
+        .take(3)
+        .map(|s| s.parse::<f64>().ok())
+        .collect::<Option<Vec<_>>>()
+        .filter(|averages| averages.len() == 3)
+        .ok_or_else(|| Error::Exec(format!("unknown content of /proc/loadavg: {}", content)))?;
+    Ok(json!({
+        "1m": averages[0],
+        "5m": averages[1],
+        "15m": averages[2],
+    }))
+}
+
+fn read_proc_file(path: &str) -> Result<String> {
+    fs::read_to_string(path)
+        .map_err(|err| Error::Exec(format!("failed to read '{}' since {}", path, err)))
+}
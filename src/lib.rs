@@ -1,13 +1,31 @@
+mod archive;
 mod argument;
+mod catalog;
+mod ckb_cli;
 mod ckb_config;
+mod ckb_log;
+mod command;
 mod config;
+mod download;
+mod encryption;
 mod error;
 mod execute;
+mod hash;
+mod host;
+mod log_summary;
+mod manifest;
+mod prompt;
 mod qiniu;
+mod redact;
 mod rpc_client;
+mod s3;
+mod service;
+mod storage;
+mod time_range;
+mod upload;
 
 pub use crate::{argument::Args, config::Config};
 
 pub mod prelude {
-    pub use crate::execute::CanExecute as _;
+    pub use crate::execute::{CanExecute as _, CanExecuteWithoutConfig as _};
 }
@@ -0,0 +1,380 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+};
+
+use chrono::{DateTime, Duration, DurationRound as _, FixedOffset};
+use log::Level;
+use regex::{Captures, Regex};
+
+use crate::{ckb_log::Record, time_range::TimeRange};
+
+const REPORT_DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S %:z";
+/// The width of the longest bar in the histogram.
+const HISTOGRAM_WIDTH: usize = 50;
+/// The histogram has at most this many rows, unless each row is more than a day.
+const MAX_HISTOGRAM_ROWS: usize = 60;
+/// Widths of rows of the histogram in minutes, the narrowest one which fits is used.
+const HISTOGRAM_ROW_MINUTES: &[i64] = &[1, 5, 15, 60, 6 * 60, 24 * 60];
+
+/// Known patterns of bad things, they are searched in records at any level.
+const ANOMALY_PATTERNS: &[(&str, &str)] = &[
+    ("panic", r"(?i)\bpanic(ked)?\b"),
+    ("ban peer", r"(?i)\bban(ned)? peer"),
+    ("fork switch", r"(?i)\bswitch(ed|ing)? fork|\bfork switch"),
+    (
+        "rocksdb error",
+        r"(?i)rocksdb.*(error|corrupt)|(error|corrupt).*rocksdb",
+    ),
+];
+
+/// Patterns of variable parts in messages, the first one which matches is replaced.
+///
+/// They are matched in one pass, so a replacement is never matched again, such as the "0" of
+/// "0x<hex>" as a number.
+const NORMALIZE_PATTERNS: &[(&str, &str)] = &[
+    (r"0x[0-9a-fA-F]+", "0x<hex>"),
+    (r"\b[0-9a-fA-F]{16,}\b", "<hash>"),
+    (r"\b(?:\d{1,3}\.){3}\d{1,3}(?::\d+)?\b", "<ip>"),
+    (r"\b(?:Qm|12D3KooW)[1-9A-HJ-NP-Za-km-z]{20,}\b", "<peer>"),
+    (r"\d+(?:\.\d+)?", "<n>"),
+];
+
+/// Statistics of records, which is a quick report before reading the raw logs.
+pub(crate) struct Summary {
+    top: usize,
+    total: usize,
+    levels: BTreeMap<Level, usize>,
+    modules: HashMap<String, usize>,
+    messages: HashMap<(Level, String, String), MessageStats>,
+    minutes: BTreeMap<DateTime<FixedOffset>, usize>,
+    anomalies: Vec<Anomaly>,
+    normalizer: Regex,
+}
+
+struct MessageStats {
+    count: usize,
+    first: DateTime<FixedOffset>,
+    last: DateTime<FixedOffset>,
+}
+
+struct Anomaly {
+    name: &'static str,
+    regex: Regex,
+    count: usize,
+    first: Option<Record>,
+    last: Option<DateTime<FixedOffset>>,
+}
+
+impl Summary {
+    /// Creates an empty summary, which lists `top` messages at most.
+    pub(crate) fn new(top: usize) -> Self {
+        let anomalies = ANOMALY_PATTERNS
+            .iter()
+            .map(|(name, pattern)| Anomaly {
+                name,
+                regex: Regex::new(pattern).expect("compile regex"),
+                count: 0,
+                first: None,
+                last: None,
+            })
+            .collect();
+        // Patterns only have non-capturing groups, so the N-th group is the N-th pattern.
+        let pattern = NORMALIZE_PATTERNS
+            .iter()
+            .map(|(pattern, _)| format!("({})", pattern))
+            .collect::<Vec<_>>()
+            .join("|");
+        let normalizer = Regex::new(&pattern).expect("compile regex");
+        Self {
+            top,
+            total: 0,
+            levels: BTreeMap::new(),
+            modules: HashMap::new(),
+            messages: HashMap::new(),
+            minutes: BTreeMap::new(),
+            anomalies,
+            normalizer,
+        }
+    }
+
+    pub(crate) fn add(&mut self, record: &Record) {
+        self.total += 1;
+        *self.levels.entry(record.level).or_default() += 1;
+        *self.modules.entry(record.target.clone()).or_default() += 1;
+        let minute = record
+            .timestamp
+            .duration_trunc(Duration::minutes(1))
+            .unwrap_or(record.timestamp);
+        *self.minutes.entry(minute).or_default() += 1;
+        if record.level <= Level::Warn {
+            let message = self.normalize(record.message.lines().next().unwrap_or_default());
+            let key = (record.level, record.target.clone(), message);
+            let stats = self.messages.entry(key).or_insert(MessageStats {
+                count: 0,
+                first: record.timestamp,
+                last: record.timestamp,
+            });
+            stats.count += 1;
+            stats.last = record.timestamp;
+        }
+        for anomaly in &mut self.anomalies {
+            if anomaly.regex.is_match(&record.message) {
+                anomaly.count += 1;
+                anomaly.last = Some(record.timestamp);
+                if anomaly.first.is_none() {
+                    anomaly.first = Some(record.clone());
+                }
+            }
+        }
+    }
+
+    fn normalize(&self, message: &str) -> String {
+        self.normalizer
+            .replace_all(message, |caps: &Captures| {
+                NORMALIZE_PATTERNS
+                    .iter()
+                    .enumerate()
+                    .find(|(index, _)| caps.get(index + 1).is_some())
+                    .map_or("", |(_, (_, replacement))| *replacement)
+            })
+            .into_owned()
+    }
+
+    /// Writes the report as plain text.
+    pub(crate) fn write_report<W: Write>(
+        &self,
+        w: &mut W,
+        time_range: &TimeRange,
+    ) -> io::Result<()> {
+        writeln!(
+            w,
+            "Time range: {} ~ {}",
+            time_range.from.format(REPORT_DATETIME_FORMAT),
+            time_range.to.format(REPORT_DATETIME_FORMAT)
+        )?;
+        writeln!(w, "Records: {}", self.total)?;
+
+        writeln!(w)?;
+        writeln!(w, "Anomalies:")?;
+        let mut has_anomaly = false;
+        for anomaly in &self.anomalies {
+            if let (Some(first), Some(last)) = (&anomaly.first, anomaly.last) {
+                has_anomaly = true;
+                writeln!(
+                    w,
+                    "  {:<16} {:>8}  {} ~ {}",
+                    anomaly.name, anomaly.count, first.timestamp, last
+                )?;
+                writeln!(w, "      {}", first.raw.lines().next().unwrap_or_default())?;
+            }
+        }
+        if !has_anomaly {
+            writeln!(w, "  (none)")?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "Levels:")?;
+        for (level, count) in &self.levels {
+            writeln!(w, "  {:<16} {:>8}", level, count)?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "Modules:")?;
+        let mut modules = self.modules.iter().collect::<Vec<_>>();
+        modules.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        for (module, count) in modules {
+            writeln!(w, "  {:<40} {:>8}", module, count)?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "Top {} repeated errors and warnings:", self.top)?;
+        let mut messages = self.messages.iter().collect::<Vec<_>>();
+        messages.sort_by(|a, b| b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(b.0)));
+        if messages.is_empty() {
+            writeln!(w, "  (none)")?;
+        }
+        for ((level, target, message), stats) in messages.into_iter().take(self.top) {
+            writeln!(
+                w,
+                "  {:>8}  {:<5} {}  {} ~ {}",
+                stats.count, level, target, stats.first, stats.last
+            )?;
+            writeln!(w, "      {}", message)?;
+        }
+
+        writeln!(w)?;
+        let (first, last) = match (
+            self.minutes.keys().next().copied(),
+            self.minutes.keys().next_back().copied(),
+        ) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                writeln!(w, "Records per minute:")?;
+                writeln!(w, "  (none)")?;
+                return Ok(());
+            }
+        };
+        let row_minutes = histogram_row_minutes(first, last);
+        let row_width = Duration::minutes(row_minutes);
+        let mut rows = BTreeMap::<DateTime<FixedOffset>, usize>::new();
+        for (minute, count) in &self.minutes {
+            *rows.entry(truncate(*minute, row_width)).or_default() += count;
+        }
+        writeln!(w, "Records per {}:", row_name(row_minutes))?;
+        let max = rows.values().copied().max().unwrap_or_default();
+        let mut row = truncate(first, row_width);
+        while row <= last {
+            let count = rows.get(&row).copied().unwrap_or_default();
+            // Any row which has records has a visible bar.
+            let width = if count == 0 {
+                0
+            } else {
+                (count * HISTOGRAM_WIDTH / max).max(1)
+            };
+            let line = format!(
+                "  {} {:>8} {}",
+                row.format("%Y-%m-%d %H:%M"),
+                count,
+                "#".repeat(width)
+            );
+            writeln!(w, "{}", line.trim_end())?;
+            row = row + row_width;
+        }
+        Ok(())
+    }
+}
+
+fn truncate(datetime: DateTime<FixedOffset>, width: Duration) -> DateTime<FixedOffset> {
+    datetime.duration_trunc(width).unwrap_or(datetime)
+}
+
+/// The narrowest width of rows in minutes, so the histogram from `first` to `last` has at most
+/// `MAX_HISTOGRAM_ROWS` rows, rows are whole days if a day is not enough.
+fn histogram_row_minutes(first: DateTime<FixedOffset>, last: DateTime<FixedOffset>) -> i64 {
+    let row_count = |minutes: i64| {
+        let width = Duration::minutes(minutes);
+        let span = truncate(last, width) - truncate(first, width);
+        span.num_minutes() / minutes + 1
+    };
+    let max_rows = MAX_HISTOGRAM_ROWS as i64;
+    if let Some(minutes) = HISTOGRAM_ROW_MINUTES
+        .iter()
+        .copied()
+        .find(|minutes| row_count(*minutes) <= max_rows)
+    {
+        return minutes;
+    }
+    let day = 24 * 60;
+    let mut minutes = ((last - first).num_minutes() / day / max_rows + 1) * day;
+    while row_count(minutes) > max_rows {
+        minutes += day;
+    }
+    minutes
+}
+
+fn row_name(minutes: i64) -> String {
+    match minutes {
+        1 => "minute".to_owned(),
+        60 => "hour".to_owned(),
+        1440 => "day".to_owned(),
+        _ if minutes % 1440 == 0 => format!("{} days", minutes / 1440),
+        _ if minutes % 60 == 0 => format!("{} hours", minutes / 60),
+        _ => format!("{} minutes", minutes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(minutes: &[i64]) -> Vec<String> {
+        let start = DateTime::parse_from_rfc3339("2021-08-20T10:00:30+00:00").unwrap();
+        let mut summary = Summary::new(10);
+        for minute in minutes {
+            let timestamp = start + Duration::minutes(*minute);
+            summary.add(&Record {
+                timestamp,
+                thread: None,
+                level: Level::Info,
+                target: "ckb_sync".to_owned(),
+                message: "record".to_owned(),
+                raw: format!("{} INFO ckb_sync  record", timestamp),
+            });
+        }
+        let time_range = TimeRange::new(start, start + Duration::days(400)).unwrap();
+        let mut report = Vec::new();
+        summary.write_report(&mut report, &time_range).unwrap();
+        String::from_utf8(report)
+            .unwrap()
+            .lines()
+            .skip_while(|line| !line.starts_with("Records per "))
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn normalize_variable_parts() {
+        let summary = Summary::new(10);
+        assert_eq!(
+            summary.normalize("block 0x1a2b at 12 from 10.0.0.1:8115 in 1.5ms"),
+            "block 0x<hex> at <n> from <ip> in <n>ms"
+        );
+        assert_eq!(
+            summary.normalize(
+                "peer QmXUJSgBJ3CAHyk2zPHQd3gBMbn4MtnCdY7fVFAUdDFS3W sent 0123456789abcdef"
+            ),
+            "peer <peer> sent <hash>"
+        );
+    }
+
+    #[test]
+    fn panic_in_any_case() {
+        let summary = Summary::new(10);
+        let panic = &summary.anomalies[0];
+        assert_eq!(panic.name, "panic");
+        assert!(panic.regex.is_match("thread 'main' Panicked at 'boom'"));
+        assert!(panic.regex.is_match("PANIC in ChainService"));
+        assert!(!panic.regex.is_match("panicking is not expected"));
+    }
+
+    #[test]
+    fn histogram_per_minute() {
+        let lines = histogram(&[0, 0, 30]);
+        assert_eq!(lines[0], "Records per minute:");
+        assert_eq!(lines.len(), 1 + 31);
+        assert_eq!(
+            lines[1],
+            format!("  2021-08-20 10:00        2 {}", "#".repeat(50))
+        );
+        assert_eq!(lines[2], "  2021-08-20 10:01        0");
+        assert_eq!(
+            lines[31],
+            format!("  2021-08-20 10:30        1 {}", "#".repeat(25))
+        );
+    }
+
+    #[test]
+    fn histogram_with_wider_rows() {
+        let lines = histogram(&[0, 59, 2 * 24 * 60]);
+        assert_eq!(lines[0], "Records per hour:");
+        assert_eq!(lines.len(), 1 + 49);
+        assert_eq!(
+            lines[1],
+            format!("  2021-08-20 10:00        2 {}", "#".repeat(50))
+        );
+
+        let lines = histogram(&[0, 3 * 60]);
+        assert_eq!(lines[0], "Records per 5 minutes:");
+        assert_eq!(lines.len(), 1 + 37);
+
+        let lines = histogram(&[0, 365 * 24 * 60]);
+        assert_eq!(lines[0], "Records per 7 days:");
+        assert!(lines.len() <= 1 + MAX_HISTOGRAM_ROWS);
+    }
+
+    #[test]
+    fn histogram_without_records() {
+        assert_eq!(histogram(&[]), ["Records per minute:", "  (none)"]);
+    }
+}
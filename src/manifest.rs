@@ -0,0 +1,104 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    hash,
+};
+
+pub(crate) const MANIFEST_FILE_NAME: &str = "MANIFEST.json";
+
+/// Sizes and checksums of all files in an archive, and where the archive comes from.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ckb_version: Option<String>,
+    /// The time window of logs in the archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) time_range: Option<TimeWindow>,
+    pub(crate) files: Vec<FileEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TimeWindow {
+    pub(crate) from: String,
+    pub(crate) to: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct FileEntry {
+    pub(crate) path: String,
+    pub(crate) size: u64,
+    pub(crate) sha256: String,
+}
+
+impl Manifest {
+    pub(crate) fn load_from_file(path: &Path) -> Result<Self> {
+        let data = fs::read(path).map_err(|err| {
+            let msg = format!("failed to read '{}' since {}", path.display(), err);
+            Error::Exec(msg)
+        })?;
+        serde_json::from_slice(&data).map_err(|err| {
+            let msg = format!("failed to parse '{}' since {}", path.display(), err);
+            Error::Exec(msg)
+        })
+    }
+
+    /// Checks all files listed in the manifest, paths are relative to `base_dir`.
+    pub(crate) fn verify(&self, base_dir: &Path) -> Result<()> {
+        for file in &self.files {
+            let path = base_dir.join(&file.path);
+            let size = fs::metadata(&path).map(|m| m.len()).map_err(|err| {
+                let msg = format!("failed to stat '{}' since {}", path.display(), err);
+                Error::Exec(msg)
+            })?;
+            if size != file.size {
+                let msg = format!(
+                    "size mismatch for '{}', expect {} but got {}",
+                    file.path, file.size, size
+                );
+                return Err(Error::Exec(msg));
+            }
+            let sha256 = hash::sha256_file(&path)?;
+            if sha256 != file.sha256 {
+                let msg = format!(
+                    "checksum mismatch for '{}', expect {} but got {}",
+                    file.path, file.sha256, sha256
+                );
+                return Err(Error::Exec(msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks all files listed in the manifest against sizes and checksums of files.
+    pub(crate) fn verify_checksums(
+        &self,
+        checksums: &HashMap<String, (u64, String)>,
+    ) -> Result<()> {
+        for file in &self.files {
+            let (size, sha256) = checksums.get(&file.path).ok_or_else(|| {
+                let msg = format!("'{}' is missing", file.path);
+                Error::Exec(msg)
+            })?;
+            if *size != file.size {
+                let msg = format!(
+                    "size mismatch for '{}', expect {} but got {}",
+                    file.path, file.size, size
+                );
+                return Err(Error::Exec(msg));
+            }
+            if *sha256 != file.sha256 {
+                let msg = format!(
+                    "checksum mismatch for '{}', expect {} but got {}",
+                    file.path, file.sha256, sha256
+                );
+                return Err(Error::Exec(msg));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+use std::io::{self, prelude::*};
+
+use crate::error::{Error, Result};
+
+/// Asks the operator to type `expected` to continue.
+pub(crate) fn confirm(question: &str, expected: &str) -> Result<bool> {
+    print!("{}\nType \"{}\" to continue: ", question, expected);
+    io::stdout()
+        .flush()
+        .map_err(|err| Error::Exec(format!("failed to flush stdout since {}", err)))?;
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .map_err(|err| Error::Exec(format!("failed to read stdin since {}", err)))?;
+    Ok(input.trim() == expected)
+}
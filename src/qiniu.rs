@@ -1,74 +1,437 @@
-use std::path::Path;
+use std::{cell::Cell, collections::BTreeMap, fs, path::Path, time::Duration as StdDuration};
 
-use qiniu_upload::{UploadProgressInfo, UploaderBuilder};
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    Method,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sha1::Sha1;
 use url::Url;
 
 use crate::{
     config::QiniuSection,
+    download,
     error::{Error, Result},
+    storage::{self, Check, Metadata, Multipart, Object, Part, Storage},
 };
 
-const UPLOAD_MIME_TYPE: &str = "application/octet-stream";
-const UPLOAD_URL: &str = "https://upload.qiniup.com";
-
-pub(crate) fn upload(cfg: &QiniuSection, file_path: &Path) -> Result<Url> {
-    let object_name = file_path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .ok_or_else(|| {
-            let msg = format!(
-                "failed to get file name from path [{}]",
-                file_path.display()
-            );
-            Error::Qiniu(msg)
+const LIST_LIMIT: &str = "1000";
+/// The TTL of URLs to download objects from a private bucket by this tool.
+const DOWNLOAD_URL_TTL: i64 = 60 * 60;
+/// The TTL of upload tokens, a new token is created for every request.
+const UPLOAD_TOKEN_TTL: i64 = 60 * 60;
+/// The size of parts of multipart uploads, Qiniu requires 1 MiB to 1 GiB.
+const PART_SIZE: u64 = 16 * 1024 * 1024;
+/// Try the next upload host if a host is not connected in time.
+const CONNECT_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+/// The timeout of requests to check the storage.
+const CHECK_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+pub(crate) struct QiniuStorage<'a> {
+    cfg: &'a QiniuSection,
+    client: Client,
+    /// The index of the upload host which is tried first, it is the last one which works.
+    upload_index: Cell<usize>,
+}
+
+#[derive(Deserialize)]
+struct ListResponse {
+    /// The marker to list the next page, it is empty if there are no more objects.
+    #[serde(default)]
+    marker: String,
+    #[serde(default)]
+    items: Vec<ListItem>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateResponse {
+    upload_id: String,
+}
+
+#[derive(Deserialize)]
+struct PartResponse {
+    etag: String,
+}
+
+#[derive(Deserialize)]
+struct ListItem {
+    key: String,
+    fsize: u64,
+}
+
+impl<'a> QiniuStorage<'a> {
+    pub(crate) fn new(cfg: &'a QiniuSection) -> Result<Self> {
+        // Uploading a part to a slow host takes a long time.
+        let client = Client::builder()
+            .timeout(None)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .map_err(|err| {
+                let msg = format!("failed to create the HTTP client since {}", err);
+                Error::Qiniu(msg)
+            })?;
+        Ok(Self {
+            cfg,
+            client,
+            upload_index: Cell::new(0),
         })
-        .map(|name| format!("{}{}", cfg.path_prefix, name))?;
-    let uploader = UploaderBuilder::new(&cfg.access_key, &cfg.secret_key, &cfg.bucket)
-        .up_urls(vec![UPLOAD_URL.to_owned()])
-        .use_https(true)
-        .build();
-    let response = uploader
-        .upload_path(file_path)
-        .map_err(|err| {
-            let msg = format!(
-                "failed to create qiniu::Uploader({}) since {}",
-                file_path.display(),
-                err
-            );
+    }
+
+    /// The URL to download the object from the domain of the bucket.
+    fn object_url(&self, key: &str) -> Result<Url> {
+        let mut url = self.cfg.domain.clone();
+        url.path_segments_mut()
+            .map_err(|_| {
+                let msg = format!("invalid [qiniu.domain] \"{}\"", self.cfg.domain);
+                Error::Cfg(msg)
+            })?
+            .pop_if_empty()
+            .extend(key.split('/'));
+        Ok(url)
+    }
+
+    /// Signs the download URL of an object in a private bucket, it expires after `ttl`.
+    ///
+    /// Ref: <https://developer.qiniu.com/kodo/1202/download-token>
+    fn sign_url(&self, mut url: Url, ttl: Duration) -> Result<Url> {
+        self.check_url_ttl(ttl)?;
+        let deadline = (Utc::now() + ttl).timestamp();
+        url.query_pairs_mut()
+            .append_pair("e", &deadline.to_string());
+        let token = self.sign(url.as_str());
+        let query = format!("{}&token={}", url.query().unwrap_or_default(), token);
+        url.set_query(Some(&query));
+        Ok(url)
+    }
+
+    /// The access key and the signature of the data.
+    fn sign(&self, data: &str) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.cfg.secret_key.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(data.as_bytes());
+        let sign = base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE);
+        format!("{}:{}", self.cfg.access_key, sign)
+    }
+
+    /// Creates a request of the management APIs, which is signed by the access token.
+    ///
+    /// Ref: <https://developer.qiniu.com/kodo/1201/access-token>
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let mut data = url.path().to_owned();
+        if let Some(query) = url.query() {
+            data.push('?');
+            data.push_str(query);
+        }
+        data.push('\n');
+        let token = format!("QBox {}", self.sign(&data));
+        self.client
+            .request(method, url)
+            .header("authorization", token)
+    }
+
+    /// The upload token which allows to create the object of the key, it never overwrites.
+    ///
+    /// Ref: <https://developer.qiniu.com/kodo/1208/upload-token>
+    fn upload_token(&self, key: &str) -> String {
+        let policy = json!({
+            "scope": format!("{}:{}", self.cfg.bucket, key),
+            "deadline": (Utc::now() + Duration::seconds(UPLOAD_TOKEN_TTL)).timestamp(),
+            "insertOnly": 1,
+        });
+        let policy = base64::encode_config(policy.to_string(), base64::URL_SAFE);
+        format!("{}:{}", self.sign(&policy), policy)
+    }
+
+    /// The URL of the multipart upload APIs of the object on the host, followed by `segments`.
+    ///
+    /// Ref: <https://developer.qiniu.com/kodo/6364/multipartupload-interface>
+    fn upload_url(&self, host_url: &Url, key: &str, segments: &[&str]) -> Url {
+        let mut url = host_url.clone();
+        url.path_segments_mut()
+            .expect("the upload URL is a base")
+            .clear()
+            .extend(&[
+                "buckets",
+                &self.cfg.bucket,
+                "objects",
+                &encode_entry(key),
+                "uploads",
+            ])
+            .extend(segments);
+        url
+    }
+
+    /// Sends a request of the upload APIs, which is signed by the upload token.
+    ///
+    /// Upload hosts are tried in order until one of them responds without a server error, and
+    /// that host is tried first by later requests.
+    fn send_upload<F>(
+        &self,
+        method: Method,
+        key: &str,
+        segments: &[&str],
+        action: &str,
+        build: F,
+    ) -> Result<Response>
+    where
+        F: Fn(RequestBuilder) -> RequestBuilder,
+    {
+        let host_urls = &self.cfg.upload_urls;
+        let first = self.upload_index.get();
+        let mut errors = Vec::new();
+        for index in (first..host_urls.len()).chain(0..first) {
+            let host_url = &host_urls[index];
+            let token = format!("UpToken {}", self.upload_token(key));
+            let request = self
+                .client
+                .request(method.clone(), self.upload_url(host_url, key, segments))
+                .header("authorization", token);
+            let error = match build(request).send() {
+                Ok(response) if !response.status().is_server_error() => {
+                    self.upload_index.set(index);
+                    return check_response(response, action);
+                }
+                Ok(response) => format!("the response is {}", response.status()),
+                Err(err) => err.to_string(),
+            };
+            log::warn!("failed to {} by {} since {}", action, host_url, error);
+            errors.push(format!("{}: {}", host_url, error));
+        }
+        let msg = format!(
+            "failed to {} since all upload hosts fail: {}",
+            action,
+            errors.join("; ")
+        );
+        Err(Error::Network(msg))
+    }
+
+    /// The URL to list objects, which start with the prefix.
+    fn list_url(&self, prefix: &str, marker: &str, limit: &str) -> Url {
+        let mut url = self
+            .cfg
+            .rsf_url
+            .join("list")
+            .expect("parse the URL of the list API");
+        url.query_pairs_mut()
+            .append_pair("bucket", &self.cfg.bucket)
+            .append_pair("marker", marker)
+            .append_pair("limit", limit)
+            .append_pair("prefix", prefix);
+        url
+    }
+
+    /// Sends the request, a response which is not successful is an error.
+    fn send(&self, request: RequestBuilder, action: &str) -> Result<Response> {
+        let response = request.send().map_err(|err| {
+            let msg = format!("failed to {} since {}", action, err);
+            Error::Network(msg)
+        })?;
+        check_response(response, action)
+    }
+}
+
+impl Storage for QiniuStorage<'_> {
+    /// Small files are uploaded as a multipart upload of one part, which is done at once.
+    fn put(&self, file_path: &Path, key: &str, metadata: &Metadata) -> Result<()> {
+        let data = fs::read(file_path).map_err(|err| {
+            let msg = format!("failed to read '{}' since {}", file_path.display(), err);
             Error::Qiniu(msg)
+        })?;
+        let upload_id = self.create(key, metadata)?;
+        let etag = self.upload_part(key, &upload_id, 1, data)?;
+        self.complete(key, &upload_id, &[Part { number: 1, etag }], metadata)
+    }
+
+    fn multipart(&self) -> Option<&dyn Multipart> {
+        Some(self)
+    }
+
+    fn url(&self, key: &str, ttl: Duration) -> Result<Url> {
+        let url = self.object_url(key)?;
+        if self.cfg.private {
+            self.sign_url(url, ttl)
+        } else {
+            Ok(url)
+        }
+    }
+
+    fn check_url_ttl(&self, ttl: Duration) -> Result<()> {
+        if ttl < Duration::seconds(1) {
+            let msg = format!("the TTL of URLs should be positive, but got {}", ttl);
+            return Err(Error::Arg(msg));
+        }
+        Ok(())
+    }
+
+    fn key_prefix(&self) -> &str {
+        &self.cfg.path_prefix
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<Object>> {
+        let mut objects = Vec::new();
+        let mut marker = String::new();
+        loop {
+            let url = self.list_url(prefix, &marker, LIST_LIMIT);
+            let response = self.send(self.request(Method::GET, url), "list objects")?;
+            let page = response.json::<ListResponse>().map_err(|err| {
+                let msg = format!("failed to parse the list of objects since {}", err);
+                Error::Qiniu(msg)
+            })?;
+            objects.extend(page.items.into_iter().map(|item| Object {
+                key: item.key,
+                size: item.fsize,
+            }));
+            if page.marker.is_empty() {
+                break;
+            }
+            marker = page.marker;
+        }
+        Ok(objects)
+    }
+
+    fn download(&self, key: &str, dst_path: &Path) -> Result<()> {
+        let url = self.url(key, Duration::seconds(DOWNLOAD_URL_TTL))?;
+        download::download(&url, dst_path)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let entry = format!("{}:{}", self.cfg.bucket, key);
+        let url = self
+            .cfg
+            .rs_url
+            .join(&format!("delete/{}", encode_entry(&entry)))
+            .expect("parse the URL of the delete API");
+        let request = self
+            .request(Method::POST, url)
+            .header("content-type", "application/x-www-form-urlencoded");
+        self.send(request, &format!("delete \"{}\"", key))
+            .map(|_| ())
+    }
+
+    /// Checks every upload host, and listing objects which also checks credentials, and the
+    /// domain to download objects.
+    fn check(&self) -> Vec<Check> {
+        // Any response means the host is reachable, even if it is an error.
+        let reach = |url: &Url| {
+            self.client
+                .get(url.clone())
+                .timeout(CHECK_TIMEOUT)
+                .send()
+                .map_err(|err| {
+                    let msg = format!("failed to connect since {}", err);
+                    Error::Qiniu(msg)
+                })
+        };
+        let mut checks = self
+            .cfg
+            .upload_urls
+            .iter()
+            .map(|url| storage::check(format!("upload {}", url), || reach(url)))
+            .collect::<Vec<_>>();
+        checks.push(storage::check(format!("list {}", self.cfg.rsf_url), || {
+            let url = self.list_url(&self.cfg.path_prefix, "", "1");
+            let request = self.request(Method::GET, url).timeout(CHECK_TIMEOUT);
+            self.send(request, "list objects")
+        }));
+        checks.push(storage::check(
+            format!("download {}", self.cfg.domain),
+            || reach(&self.cfg.domain),
+        ));
+        checks
+    }
+}
+
+impl Multipart for QiniuStorage<'_> {
+    fn part_size(&self) -> u64 {
+        PART_SIZE
+    }
+
+    /// The metadata is set when the upload is completed.
+    fn create(&self, key: &str, _metadata: &Metadata) -> Result<String> {
+        let action = format!("start the upload of \"{}\"", key);
+        self.send_upload(Method::POST, key, &[], &action, |request| request)?
+            .json::<CreateResponse>()
+            .map(|response| response.upload_id)
+            .map_err(|err| {
+                let msg = format!("failed to parse the response since {}", err);
+                Error::Qiniu(msg)
+            })
+    }
+
+    fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: u32,
+        data: Vec<u8>,
+    ) -> Result<String> {
+        let action = format!("upload part {} of \"{}\"", number, key);
+        let segments = [upload_id, &number.to_string()];
+        self.send_upload(Method::PUT, key, &segments, &action, |request| {
+            request
+                .header("content-type", "application/octet-stream")
+                .header("content-length", data.len())
+                .body(data.clone())
         })?
-        .mime_type(UPLOAD_MIME_TYPE)
-        .object_name(object_name)
-        .upload_progress_callback(Box::new(|progress: &UploadProgressInfo| {
-            log::trace!(
-                "upload progress: upload id: {}, part number: {}, uploaded: {}",
-                progress.upload_id(),
-                progress.part_number(),
-                progress.uploaded()
-            );
-            Ok(())
-        }))
-        .start()
+        .json::<PartResponse>()
+        .map(|response| response.etag)
         .map_err(|err| {
-            let msg = format!(
-                "failed to run qiniu::upload({}) since {}",
-                file_path.display(),
-                err
-            );
-            Error::Qiniu(msg)
-        })?;
-    let url = response
-        .response_body()
-        .get("key")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| {
-            let msg = "failed to parse qiniu response from key field".to_owned();
+            let msg = format!("failed to parse the response since {}", err);
             Error::Qiniu(msg)
         })
-        .map(|key| format!("{}/{}", &cfg.domain, key))
-        .and_then(|s| {
-            Url::parse(&s)
-                .map_err(|err| Error::Cfg(format!("failed to parse response since {}", err)))
-        })?;
-    Ok(url)
+    }
+
+    fn complete(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[Part],
+        metadata: &Metadata,
+    ) -> Result<()> {
+        let action = format!("complete the upload of \"{}\"", key);
+        let parts = parts
+            .iter()
+            .map(|part| json!({ "partNumber": part.number, "etag": part.etag }))
+            .collect::<Vec<_>>();
+        let fields = metadata
+            .fields
+            .iter()
+            .map(|(name, value)| (format!("x-qn-meta-{}", name), value))
+            .collect::<BTreeMap<_, _>>();
+        let body = json!({
+            "parts": parts,
+            "fname": key.rsplit('/').next().unwrap_or(key),
+            "mimeType": metadata.content_type,
+            "metadata": fields,
+        });
+        self.send_upload(Method::POST, key, &[upload_id], &action, |request| {
+            request.json(&body)
+        })
+        .map(|_| ())
+    }
+}
+
+/// A response which is not successful is an error.
+fn check_response(response: Response, action: &str) -> Result<Response> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().unwrap_or_default();
+        let msg = format!(
+            "failed to {} since the response is {}: {}",
+            action, status, body
+        );
+        if storage::is_transient(status) {
+            return Err(Error::Network(msg));
+        }
+        return Err(Error::Qiniu(msg));
+    }
+    Ok(response)
+}
+
+fn encode_entry(entry: &str) -> String {
+    base64::encode_config(entry, base64::URL_SAFE)
 }
@@ -0,0 +1,252 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::{BufRead as _, BufReader, BufWriter, Write as _},
+    net::{Ipv4Addr, Ipv6Addr},
+    os::unix::fs::OpenOptionsExt as _,
+    path::Path,
+    str::FromStr as _,
+};
+
+use regex::{Captures, Regex};
+
+use crate::{
+    ckb_log::Record,
+    error::{Error, Result},
+};
+
+const IPV4_REGEX: &str = r"\b(?:\d{1,3}\.){3}\d{1,3}\b";
+/// Candidates of IPv6 addresses, they are checked by parsing since the regex is loose.
+///
+/// A candidate starts with a group or "::", but not a single ":", otherwise it would take the
+/// separator in "addr:2001:db8::1" and be skipped as a part of a word.
+const IPV6_REGEX: &str =
+    r"(?:[0-9a-fA-F]{1,4}(?::[0-9a-fA-F]{0,4}){2,7}|:(?::[0-9a-fA-F]{0,4}){1,7})";
+/// Peer IDs are base58 multihashes, such as "QmNv..." or "12D3KooW...".
+const PEER_ID_REGEX: &str = r"\b(?:Qm|12D3KooW)[1-9A-HJ-NP-Za-km-z]{20,}\b";
+
+/// Masks peer IP addresses, peer IDs and secrets in texts.
+///
+/// The same value is always replaced by the same pseudonym, so records of a peer still can be
+/// followed after redacted.
+pub(crate) struct Redactor {
+    ipv4: Regex,
+    ipv6: Regex,
+    peer_id: Regex,
+    secrets: Vec<String>,
+    pseudonyms: RefCell<Pseudonyms>,
+}
+
+#[derive(Default)]
+struct Pseudonyms {
+    /// Pseudonyms of original values.
+    values: BTreeMap<String, String>,
+    ip_count: usize,
+    peer_count: usize,
+}
+
+impl Redactor {
+    pub(crate) fn new(secrets: &[&str]) -> Self {
+        let mut secrets = secrets
+            .iter()
+            .filter(|secret| !secret.is_empty())
+            .map(|secret| (*secret).to_owned())
+            .collect::<Vec<_>>();
+        // Replace longer secrets first, in case that a secret contains another one.
+        secrets.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        secrets.dedup();
+        Self {
+            ipv4: Regex::new(IPV4_REGEX).expect("compile regex"),
+            ipv6: Regex::new(IPV6_REGEX).expect("compile regex"),
+            peer_id: Regex::new(PEER_ID_REGEX).expect("compile regex"),
+            secrets,
+            pseudonyms: RefCell::default(),
+        }
+    }
+
+    pub(crate) fn redact(&self, text: &str) -> String {
+        let mut text = text.to_owned();
+        for secret in &self.secrets {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), "<secret>");
+            }
+        }
+        let text = self.replace(&text, Kind::PeerId);
+        let text = self.replace(&text, Kind::Ipv4);
+        self.replace(&text, Kind::Ipv6)
+    }
+
+    pub(crate) fn redact_record(&self, record: &Record) -> Record {
+        let mut redacted = record.clone();
+        redacted.message = self.redact(&record.message);
+        redacted.raw = self.redact(&record.raw);
+        redacted
+    }
+
+    /// Redacts a text file line by line.
+    pub(crate) fn redact_file(&self, src_path: &Path, dst_path: &Path) -> Result<()> {
+        let read_file = OpenOptions::new()
+            .read(true)
+            .open(src_path)
+            .map(BufReader::new)
+            .map_err(|err| {
+                let msg = format!("failed to open '{}' since {}", src_path.display(), err);
+                Error::Exec(msg)
+            })?;
+        let mut write_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dst_path)
+            .map(BufWriter::new)
+            .map_err(|err| {
+                let msg = format!("failed to create '{}' since {}", dst_path.display(), err);
+                Error::Exec(msg)
+            })?;
+        for line in read_file.lines() {
+            let line = line.map_err(|err| {
+                let msg = format!("failed to read '{}' since {}", src_path.display(), err);
+                Error::Exec(msg)
+            })?;
+            writeln!(write_file, "{}", self.redact(&line)).map_err(|err| {
+                let msg = format!("failed to write '{}' since {}", dst_path.display(), err);
+                Error::Exec(msg)
+            })?;
+        }
+        write_file.flush().map_err(|err| {
+            let msg = format!("failed to write '{}' since {}", dst_path.display(), err);
+            Error::Exec(msg)
+        })
+    }
+
+    /// Saves pseudonyms and their original values, only the owner can read the file since it
+    /// reveals what are redacted.
+    pub(crate) fn save_mapping(&self, path: &Path) -> Result<()> {
+        let pseudonyms = self.pseudonyms.borrow();
+        let mapping = pseudonyms
+            .values
+            .iter()
+            .map(|(original, pseudonym)| (pseudonym, original))
+            .collect::<BTreeMap<_, _>>();
+        let content = serde_json::to_string_pretty(&mapping).map_err(|err| {
+            let msg = format!("failed to serialize the redaction mapping since {}", err);
+            Error::Exec(msg)
+        })?;
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .map_err(|err| {
+                let msg = format!("failed to write '{}' since {}", path.display(), err);
+                Error::Exec(msg)
+            })
+    }
+
+    fn replace(&self, text: &str, kind: Kind) -> String {
+        let regex = match kind {
+            Kind::Ipv4 => &self.ipv4,
+            Kind::Ipv6 => &self.ipv6,
+            Kind::PeerId => &self.peer_id,
+        };
+        regex
+            .replace_all(text, |caps: &Captures| {
+                let matched = caps.get(0).expect("the whole match");
+                let value = matched.as_str();
+                // The loose IPv6 regex also matches parts of paths, such as "d::" in
+                // "ckb_bin::subcommand".
+                let is_in_word = text[..matched.start()]
+                    .chars()
+                    .next_back()
+                    .into_iter()
+                    .chain(text[matched.end()..].chars().next())
+                    .any(|c| c.is_alphanumeric() || c == '_');
+                if !is_in_word && kind.should_redact(value) {
+                    self.pseudonym(value, kind)
+                } else {
+                    value.to_owned()
+                }
+            })
+            .into_owned()
+    }
+
+    fn pseudonym(&self, value: &str, kind: Kind) -> String {
+        let mut pseudonyms = self.pseudonyms.borrow_mut();
+        if let Some(pseudonym) = pseudonyms.values.get(value) {
+            return pseudonym.clone();
+        }
+        let pseudonym = match kind {
+            Kind::Ipv4 | Kind::Ipv6 => {
+                pseudonyms.ip_count += 1;
+                format!("<ip-{}>", pseudonyms.ip_count)
+            }
+            Kind::PeerId => {
+                pseudonyms.peer_count += 1;
+                format!("<peer-{}>", pseudonyms.peer_count)
+            }
+        };
+        pseudonyms
+            .values
+            .insert(value.to_owned(), pseudonym.clone());
+        pseudonym
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Ipv4,
+    Ipv6,
+    PeerId,
+}
+
+impl Kind {
+    /// Local addresses, such as the RPC listen address, do not reveal anything.
+    fn should_redact(self, value: &str) -> bool {
+        match self {
+            Self::Ipv4 => Ipv4Addr::from_str(value)
+                .map(|ip| !ip.is_loopback() && !ip.is_unspecified())
+                .unwrap_or(false),
+            Self::Ipv6 => Ipv6Addr::from_str(value)
+                .map(|ip| !ip.is_loopback() && !ip.is_unspecified())
+                .unwrap_or(false),
+            Self::PeerId => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_ipv6_after_a_separator() {
+        let redactor = Redactor::new(&[]);
+        assert_eq!(redactor.redact("addr:2001:db8::1"), "addr:<ip-1>");
+        assert_eq!(
+            redactor.redact("dial 2001:db8::1 and [2001:db8::2]:8115"),
+            "dial <ip-1> and [<ip-2>]:8115"
+        );
+    }
+
+    #[test]
+    fn keep_paths_and_local_addresses() {
+        let redactor = Redactor::new(&[]);
+        let text = "ckb_bin::subcommand::run listen on ::1 and [::]:8114, 127.0.0.1:8114";
+        assert_eq!(redactor.redact(text), text);
+    }
+
+    #[test]
+    fn redact_peers_and_secrets() {
+        let redactor = Redactor::new(&["0xc8328aabcd9b9e8e"]);
+        let peer_id = "QmNQ4jky6uVqLDrPU7snqxARuNGWNLgSrTnssbRuy3ij2W";
+        let text = format!(
+            "ban peer {} at /ip4/47.111.169.36/tcp/8111, args 0xc8328aabcd9b9e8e, again {}",
+            peer_id, peer_id
+        );
+        assert_eq!(
+            redactor.redact(&text),
+            "ban peer <peer-1> at /ip4/<ip-1>/tcp/8111, args <secret>, again <peer-1>"
+        );
+    }
+}
@@ -119,6 +119,10 @@ macro_rules! serialize_parameters {
 jsonrpc!(
     trait Client {
         fn get_peers(&self) -> Vec<rpc::RemoteNode>;
+        fn local_node_info(&self) -> rpc::LocalNode;
+        fn sync_state(&self) -> rpc::SyncState;
+        fn tx_pool_info(&self) -> rpc::TxPoolInfo;
+        fn get_blockchain_info(&self) -> rpc::ChainInfo;
     }
 );
 
@@ -135,4 +139,20 @@ impl RpcClient {
     pub(crate) fn get_peers(&self) -> Result<Vec<rpc::RemoteNode>> {
         self.inner.get_peers()
     }
+
+    pub(crate) fn local_node_info(&self) -> Result<rpc::LocalNode> {
+        self.inner.local_node_info()
+    }
+
+    pub(crate) fn sync_state(&self) -> Result<rpc::SyncState> {
+        self.inner.sync_state()
+    }
+
+    pub(crate) fn tx_pool_info(&self) -> Result<rpc::TxPoolInfo> {
+        self.inner.tx_pool_info()
+    }
+
+    pub(crate) fn get_blockchain_info(&self) -> Result<rpc::ChainInfo> {
+        self.inner.get_blockchain_info()
+    }
 }
@@ -0,0 +1,117 @@
+use std::{
+    collections::BTreeMap,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    command,
+    error::{Error, Result},
+    time_range::TimeRange,
+};
+
+/// Properties of the unit which are useful for diagnosis.
+const STATUS_PROPERTIES: &[&str] = &[
+    "Id",
+    "LoadState",
+    "ActiveState",
+    "SubState",
+    "Result",
+    "MainPID",
+    "ExecMainStartTimestamp",
+    "ExecMainExitTimestamp",
+    "ExecMainCode",
+    "ExecMainStatus",
+    "NRestarts",
+    "MemoryCurrent",
+    "TasksCurrent",
+    "LimitNOFILE",
+    "ActiveEnterTimestamp",
+    "InactiveEnterTimestamp",
+];
+
+/// The systemd unit which runs CKB.
+pub(crate) struct Service<'a> {
+    name: &'a str,
+}
+
+// Slices are passed to `Command::args` since arrays are not `IntoIterator` before Rust 1.53.
+#[allow(unknown_lints, clippy::needless_borrows_for_generic_args)]
+impl<'a> Service<'a> {
+    pub(crate) fn new(name: &'a str) -> Self {
+        Self { name }
+    }
+
+    pub(crate) fn is_active(&self) -> Result<bool> {
+        Command::new("systemctl")
+            .args(&["is-active", "--quiet", self.name])
+            .status()
+            .map(|status| status.success())
+            .map_err(|err| {
+                let msg = format!(
+                    "failed to check if service \"{}\" is active since {}",
+                    self.name, err
+                );
+                Error::Exec(msg)
+            })
+    }
+
+    /// Returns whether the unit is active, which is an error unless it can be stopped first.
+    pub(crate) fn check_stoppable(&self, stop_first: bool) -> Result<bool> {
+        let is_active = self.is_active()?;
+        if is_active && !stop_first {
+            let msg = format!(
+                "service \"{}\" is running, stop it first or use `--stop-first`",
+                self.name
+            );
+            return Err(Error::Exec(msg));
+        }
+        Ok(is_active)
+    }
+
+    pub(crate) fn start(&self) -> Result<()> {
+        log::info!("start service \"{}\"", self.name);
+        command::run(Command::new("systemctl").args(&["start", self.name]))
+    }
+
+    pub(crate) fn stop(&self) -> Result<()> {
+        log::info!("stop service \"{}\"", self.name);
+        command::run(Command::new("systemctl").args(&["stop", self.name]))
+    }
+
+    /// Returns the status of the unit as properties.
+    pub(crate) fn status(&self) -> Result<BTreeMap<String, String>> {
+        let output = command::output(
+            Command::new("systemctl")
+                .args(&["show", "--no-pager"])
+                .arg(format!("--property={}", STATUS_PROPERTIES.join(",")))
+                .arg(self.name),
+        )?;
+        let properties = output
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) => Some((key.to_owned(), value.to_owned())),
+                    _ => None,
+                }
+            })
+            .collect();
+        Ok(properties)
+    }
+
+    /// Writes the journal of the unit in the time range into `stdout`.
+    pub(crate) fn journal(&self, time_range: &TimeRange, stdout: Stdio) -> Result<()> {
+        let mut command = Command::new("journalctl");
+        command
+            .args(&[
+                "--no-pager",
+                "--output",
+                "short-iso-precise",
+                "--unit",
+                self.name,
+            ])
+            .arg(format!("--since=@{}", time_range.from.timestamp()))
+            .arg(format!("--until=@{}", time_range.to.timestamp()));
+        command::run_with_stdout(&mut command, stdout)
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration as StdDuration, Instant},
+};
+
+use chrono::Duration;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use walkdir::WalkDir;
+
+use crate::{
+    config::{LocalSection, StorageSection},
+    error::{Error, Result},
+    qiniu::QiniuStorage,
+    s3::S3Storage,
+};
+
+/// Download URLs of uploaded archives are valid for 1 day by default.
+pub(crate) const DEFAULT_URL_TTL_DAYS: i64 = 1;
+
+/// A destination of archives.
+pub(crate) trait Storage {
+    /// Uploads the file as the object of the key in one go.
+    fn put(&self, file_path: &Path, key: &str, metadata: &Metadata) -> Result<()>;
+    /// Multipart uploads, large files are uploaded by them if the backend supports them.
+    fn multipart(&self) -> Option<&dyn Multipart> {
+        None
+    }
+    /// The URL to download the object, it expires after `ttl` if the storage is private.
+    fn url(&self, key: &str, ttl: Duration) -> Result<Url>;
+    /// Checks whether URLs could expire after `ttl`, before anything is uploaded.
+    fn check_url_ttl(&self, _ttl: Duration) -> Result<()> {
+        Ok(())
+    }
+    /// The prefix of keys of uploaded objects, it is "{prefix}" in templates of keys.
+    fn key_prefix(&self) -> &str;
+    /// Lists all objects whose keys start with `prefix`.
+    fn list(&self, prefix: &str) -> Result<Vec<Object>>;
+    fn download(&self, key: &str, dst_path: &Path) -> Result<()>;
+    fn delete(&self, key: &str) -> Result<()>;
+    /// Checks whether the storage is reachable, each endpoint is checked alone.
+    fn check(&self) -> Vec<Check>;
+}
+
+/// An uploaded object.
+pub(crate) struct Object {
+    pub(crate) key: String,
+    pub(crate) size: u64,
+}
+
+/// What an uploaded object is, besides its data.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Metadata {
+    pub(crate) content_type: String,
+    /// User-defined metadata, such as the host which the archive is from.
+    pub(crate) fields: BTreeMap<String, String>,
+}
+
+/// The result of checking an endpoint of a storage.
+pub(crate) struct Check {
+    /// What is checked, such as "upload https://upload.qiniup.com".
+    pub(crate) target: String,
+    /// How long it takes to respond.
+    pub(crate) result: Result<StdDuration>,
+}
+
+/// Uploads which are split into parts, so a failed part could be uploaded again alone.
+pub(crate) trait Multipart {
+    /// The minimal size of parts, except the last one.
+    fn part_size(&self) -> u64;
+    /// Starts a multipart upload of the key, and returns the upload ID.
+    fn create(&self, key: &str, metadata: &Metadata) -> Result<String>;
+    /// Uploads a part, part numbers start from 1, and returns the ETag of the part.
+    fn upload_part(&self, key: &str, upload_id: &str, number: u32, data: Vec<u8>)
+        -> Result<String>;
+    /// Assembles all parts into the object, the metadata is the same as when it is created.
+    fn complete(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[Part],
+        metadata: &Metadata,
+    ) -> Result<()>;
+}
+
+/// An uploaded part of a multipart upload.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Part {
+    pub(crate) number: u32,
+    pub(crate) etag: String,
+}
+
+/// A local directory, it could be a mounted NFS.
+///
+/// Files have no metadata, their types are known by their extensions.
+pub(crate) struct LocalStorage<'a> {
+    cfg: &'a LocalSection,
+}
+
+/// Opens the backend which is selected in the config.
+pub(crate) fn open(cfg: &StorageSection) -> Result<Box<dyn Storage + '_>> {
+    let storage: Box<dyn Storage> = match cfg {
+        StorageSection::Qiniu(inner) => Box::new(QiniuStorage::new(inner)?),
+        StorageSection::Local(inner) => Box::new(LocalStorage::new(inner)),
+        StorageSection::S3(inner) => Box::new(S3Storage::new(inner)?),
+    };
+    Ok(storage)
+}
+
+/// Whether the request could succeed if it is sent again.
+pub(crate) fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Checks the target by running the action, and measures how long it takes.
+pub(crate) fn check<T, F>(target: String, f: F) -> Check
+where
+    F: FnOnce() -> Result<T>,
+{
+    let start = Instant::now();
+    let result = f().map(|_| start.elapsed());
+    Check { target, result }
+}
+
+/// The name of the uploaded object, which is the file name.
+pub(crate) fn object_name(file_path: &Path) -> Result<&str> {
+    file_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| {
+            let msg = format!(
+                "failed to get file name from path [{}]",
+                file_path.display()
+            );
+            Error::Storage(msg)
+        })
+}
+
+impl<'a> LocalStorage<'a> {
+    pub(crate) fn new(cfg: &'a LocalSection) -> Self {
+        Self { cfg }
+    }
+}
+
+impl LocalStorage<'_> {
+    /// Keys are relative paths which are separated by "/", they should not point outside the
+    /// directory, and hidden files are not objects.
+    fn path_of(&self, key: &str) -> Result<PathBuf> {
+        if key
+            .split('/')
+            .any(|name| name.is_empty() || name.starts_with('.'))
+        {
+            let msg = format!("invalid key \"{}\"", key);
+            return Err(Error::Storage(msg));
+        }
+        Ok(self.cfg.dir.join(key))
+    }
+}
+
+impl Storage for LocalStorage<'_> {
+    fn put(&self, file_path: &Path, key: &str, _metadata: &Metadata) -> Result<()> {
+        let dst_path = self.path_of(key)?;
+        let dst_dir = dst_path.parent().expect("parent of the file");
+        fs::create_dir_all(dst_dir).map_err(|err| {
+            let msg = format!(
+                "failed to create directory '{}' since {}",
+                dst_dir.display(),
+                err
+            );
+            Error::Storage(msg)
+        })?;
+        if dst_path.exists() {
+            let msg = format!("'{}' already exists", dst_path.display());
+            return Err(Error::Storage(msg));
+        }
+        // Copy to a hidden file first, so a partial copy never looks like an archive.
+        let part_path = {
+            let name = dst_path.file_name().expect("file name").to_string_lossy();
+            dst_dir.join(format!(".{}.part", name))
+        };
+        fs::copy(file_path, &part_path)
+            .and_then(|_| fs::rename(&part_path, &dst_path))
+            .map_err(|err| {
+                let _ = fs::remove_file(&part_path);
+                let msg = format!(
+                    "failed to copy '{}' to '{}' since {}",
+                    file_path.display(),
+                    dst_path.display(),
+                    err
+                );
+                Error::Storage(msg)
+            })
+    }
+
+    /// Files are shared by the file system, such as NFS, so URLs never expire.
+    fn url(&self, key: &str, _ttl: Duration) -> Result<Url> {
+        let path = self.path_of(key)?;
+        Url::from_file_path(&path).map_err(|_| {
+            let msg = format!("failed to convert '{}' to a URL", path.display());
+            Error::Storage(msg)
+        })
+    }
+
+    fn key_prefix(&self) -> &str {
+        ""
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<Object>> {
+        let mut objects = Vec::new();
+        let entries = WalkDir::new(&self.cfg.dir)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
+        for entry in entries {
+            let entry = entry.map_err(|err| {
+                let msg = format!("failed to read '{}' since {}", self.cfg.dir.display(), err);
+                Error::Storage(msg)
+            })?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let key = entry
+                .path()
+                .strip_prefix(&self.cfg.dir)
+                .expect("files are in the directory")
+                .iter()
+                .map(|name| name.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            let metadata = entry.metadata().map_err(|err| {
+                let msg = format!("failed to stat '{}' since {}", entry.path().display(), err);
+                Error::Storage(msg)
+            })?;
+            objects.push(Object {
+                key,
+                size: metadata.len(),
+            });
+        }
+        Ok(objects)
+    }
+
+    fn download(&self, key: &str, dst_path: &Path) -> Result<()> {
+        let src_path = self.path_of(key)?;
+        fs::copy(&src_path, dst_path).map(|_| ()).map_err(|err| {
+            let msg = format!(
+                "failed to copy '{}' to '{}' since {}",
+                src_path.display(),
+                dst_path.display(),
+                err
+            );
+            Error::Storage(msg)
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let path = self.path_of(key)?;
+        fs::remove_file(&path).map_err(|err| {
+            let msg = format!("failed to remove '{}' since {}", path.display(), err);
+            Error::Storage(msg)
+        })
+    }
+
+    fn check(&self) -> Vec<Check> {
+        let target = format!("directory {}", self.cfg.dir.display());
+        let check = check(target, || {
+            fs::read_dir(&self.cfg.dir).map_err(|err| {
+                let msg = format!("failed to read '{}' since {}", self.cfg.dir.display(), err);
+                Error::Storage(msg)
+            })
+        });
+        vec![check]
+    }
+}
@@ -0,0 +1,145 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDateTime, TimeZone as _};
+
+/// Formats of naive datetimes, which are in the timezone of the host.
+const NAIVE_DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+/// Durations are at most 100 years, longer ones are mistakes, and they overflow datetimes.
+const MAX_DURATION_MILLIS: i64 = 100 * 365 * 24 * 3600 * 1000;
+
+/// A closed time interval.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeRange {
+    pub(crate) from: DateTime<FixedOffset>,
+    pub(crate) to: DateTime<FixedOffset>,
+}
+
+impl TimeRange {
+    pub(crate) fn new(
+        from: DateTime<FixedOffset>,
+        to: DateTime<FixedOffset>,
+    ) -> Result<Self, String> {
+        if from > to {
+            Err(format!("the start {} is after the end {}", from, to))
+        } else {
+            Ok(Self { from, to })
+        }
+    }
+
+    /// The range which has `width` in total and is centered on `around`.
+    pub(crate) fn around(around: DateTime<FixedOffset>, width: Duration) -> Result<Self, String> {
+        let half = width / 2;
+        Ok(Self {
+            from: checked_sub(around, half)?,
+            to: checked_add(around, half)?,
+        })
+    }
+}
+
+pub(crate) fn now() -> DateTime<FixedOffset> {
+    Local::now().into()
+}
+
+pub(crate) fn checked_add(
+    dt: DateTime<FixedOffset>,
+    dur: Duration,
+) -> Result<DateTime<FixedOffset>, String> {
+    dt.checked_add_signed(dur)
+        .ok_or_else(|| format!("{} after {} is out of range", dur, dt))
+}
+
+pub(crate) fn checked_sub(
+    dt: DateTime<FixedOffset>,
+    dur: Duration,
+) -> Result<DateTime<FixedOffset>, String> {
+    dt.checked_sub_signed(dur)
+        .ok_or_else(|| format!("{} before {} is out of range", dur, dt))
+}
+
+/// Parses a datetime expression.
+///
+/// Supported expressions:
+/// - RFC3339, for example, "2014-11-28T21:00:09+09:00".
+/// - Naive datetimes in the timezone of the host, for example, "2014-11-28 21:00:09".
+/// - "now", optionally with an offset, for example, "now-15m" or "now+1h".
+/// - A duration which means the time before now, for example, "2h".
+pub(crate) fn parse_datetime(
+    s: &str,
+    now: DateTime<FixedOffset>,
+) -> Result<DateTime<FixedOffset>, String> {
+    let s = s.trim();
+    if let Some(offset) = s.strip_prefix("now") {
+        let offset = offset.trim();
+        return if offset.is_empty() {
+            Ok(now)
+        } else if let Some(dur) = offset.strip_prefix('-') {
+            parse_duration(dur).and_then(|dur| checked_sub(now, dur))
+        } else if let Some(dur) = offset.strip_prefix('+') {
+            parse_duration(dur).and_then(|dur| checked_add(now, dur))
+        } else {
+            Err(format!("invalid offset \"{}\" of now", offset))
+        };
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt);
+    }
+    for fmt in NAIVE_DATETIME_FORMATS {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(s, fmt) {
+            return Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(Into::into)
+                .ok_or_else(|| format!("\"{}\" does not exist in the local timezone", s));
+        }
+    }
+    // Datetimes above have separators, so the rest which starts with digits is a duration.
+    if s.starts_with(|c: char| c.is_ascii_digit()) && s.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return parse_duration(s).and_then(|dur| checked_sub(now, dur));
+    }
+    Err(format!("unknown datetime \"{}\"", s))
+}
+
+/// Parses a duration, for example, "90s", "15m", "2h", "1d" or "1h30m", it is at most 100 years.
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty duration".to_owned());
+    }
+    let too_long = || format!("duration \"{}\" is longer than 100 years", s);
+    let mut total_millis = 0i64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits_len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits_len == 0 {
+            return Err(format!("invalid duration \"{}\"", s));
+        }
+        let (digits, tail) = rest.split_at(digits_len);
+        let value = digits
+            .parse::<i64>()
+            .map_err(|err| format!("invalid duration \"{}\" since {}", s, err))?;
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let unit_millis = match unit {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 3600 * 1000,
+            "d" => 24 * 3600 * 1000,
+            _ => return Err(format!("invalid unit \"{}\" in duration \"{}\"", unit, s)),
+        };
+        total_millis = value
+            .checked_mul(unit_millis)
+            .and_then(|millis| total_millis.checked_add(millis))
+            .filter(|millis| *millis <= MAX_DURATION_MILLIS)
+            .ok_or_else(too_long)?;
+        rest = tail;
+    }
+    Ok(Duration::milliseconds(total_millis))
+}
@@ -0,0 +1,290 @@
+use std::{
+    fs::{self, File},
+    io::{Read as _, Seek as _, SeekFrom},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration as StdDuration, UNIX_EPOCH},
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    execute,
+    storage::{Metadata, Multipart, Part, Storage},
+};
+
+/// The extension which is appended to the name of the uploaded file, for its upload state.
+const STATE_EXTENSION: &str = "upload";
+/// A request is tried at most 5 times.
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: StdDuration = StdDuration::from_secs(2);
+const MAX_BACKOFF: StdDuration = StdDuration::from_secs(60);
+/// The limit of the count of parts, both S3 and Qiniu allow 10000 parts at most.
+const MAX_PARTS: u64 = 10000;
+const PROGRESS_TEMPLATE: &str = "{bar:40} {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta}";
+
+/// The state of a multipart upload, it is saved next to the uploaded file after each part, so
+/// the upload could be resumed by `ckbdev backup resume`.
+#[derive(Serialize, Deserialize)]
+struct State {
+    key: String,
+    upload_id: String,
+    /// The size and the modified time of the file, it should not be changed during the upload.
+    file_size: u64,
+    modified: u64,
+    part_size: u64,
+    /// Uploaded parts, in order.
+    parts: Vec<Part>,
+    metadata: Metadata,
+    /// The file is removed after uploaded, such as an encrypted copy of the archive.
+    is_temporary: bool,
+}
+
+/// Uploads the file as the object of the key.
+///
+/// Files larger than a part are uploaded part by part if the backend supports it, requests which
+/// fail by network errors are tried again, and a failed upload could be resumed by [`resume`]
+/// later.
+pub(crate) fn upload(
+    storage: &dyn Storage,
+    file_path: &Path,
+    key: &str,
+    metadata: &Metadata,
+    is_temporary: bool,
+) -> Result<()> {
+    let (file_size, modified) = stat(file_path)?;
+    match storage.multipart() {
+        Some(multipart) if file_size > multipart.part_size() => {
+            let action = format!("start the upload of '{}'", file_path.display());
+            let upload_id = retry(&action, || multipart.create(key, metadata))?;
+            let part_size = {
+                let min_size = ceil_div(file_size, MAX_PARTS);
+                multipart.part_size().max(min_size)
+            };
+            let state = State {
+                key: key.to_owned(),
+                upload_id,
+                file_size,
+                modified,
+                part_size,
+                parts: Vec::new(),
+                metadata: metadata.clone(),
+                is_temporary,
+            };
+            state.save(&state_path(file_path))?;
+            upload_parts(multipart, file_path, state)
+        }
+        _ => {
+            let action = format!("upload '{}'", file_path.display());
+            retry(&action, || storage.put(file_path, key, metadata))?;
+            if is_temporary {
+                remove_file(file_path)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Resumes the multipart upload of the file, and returns the key of the object.
+pub(crate) fn resume(storage: &dyn Storage, file_path: &Path) -> Result<String> {
+    let state_path = state_path(file_path);
+    if !state_path.exists() {
+        let msg = format!(
+            "no upload to resume since '{}' does not exist",
+            state_path.display()
+        );
+        return Err(Error::Exec(msg));
+    }
+    let state = State::load(&state_path)?;
+    if stat(file_path)? != (state.file_size, state.modified) {
+        let msg = format!(
+            "'{}' is changed after the upload started",
+            file_path.display()
+        );
+        return Err(Error::Exec(msg));
+    }
+    let multipart = storage.multipart().ok_or_else(|| {
+        let msg = "the storage does not support multipart uploads".to_owned();
+        Error::Storage(msg)
+    })?;
+    let key = state.key.clone();
+    upload_parts(multipart, file_path, state)?;
+    Ok(key)
+}
+
+/// Whether the upload of the file could be resumed.
+pub(crate) fn is_resumable(file_path: &Path) -> bool {
+    state_path(file_path).exists()
+}
+
+/// Moves the file and its upload state.
+pub(crate) fn rename(src_path: &Path, dst_path: &Path) -> Result<()> {
+    execute::rename(src_path, dst_path)?;
+    let src_state_path = state_path(src_path);
+    if src_state_path.exists() {
+        execute::rename(&src_state_path, &state_path(dst_path))?;
+    }
+    Ok(())
+}
+
+/// Uploads the rest parts, and completes the upload.
+fn upload_parts(multipart: &dyn Multipart, file_path: &Path, mut state: State) -> Result<()> {
+    let state_path = state_path(file_path);
+    let mut file = File::open(file_path).map_err(|err| {
+        let msg = format!("failed to open '{}' since {}", file_path.display(), err);
+        Error::Exec(msg)
+    })?;
+    let count = ceil_div(state.file_size, state.part_size);
+    let progress = progress_bar(state.file_size);
+    let uploaded = state.part_size * state.parts.len() as u64;
+    progress.set_position(uploaded.min(state.file_size));
+    progress.reset_eta();
+    for number in (state.parts.len() as u64 + 1)..=count {
+        let offset = state.part_size * (number - 1);
+        let size = state.part_size.min(state.file_size - offset);
+        let mut data = vec![0; size as usize];
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut data))
+            .map_err(|err| {
+                let msg = format!("failed to read '{}' since {}", file_path.display(), err);
+                Error::Exec(msg)
+            })?;
+        let number = number as u32;
+        let action = format!(
+            "upload part {}/{} of '{}'",
+            number,
+            count,
+            file_path.display()
+        );
+        let etag = retry(&action, || {
+            multipart.upload_part(&state.key, &state.upload_id, number, data.clone())
+        })?;
+        log::trace!(
+            "upload progress: upload id: {}, part number: {}, uploaded: {}",
+            state.upload_id,
+            number,
+            offset + size
+        );
+        state.parts.push(Part { number, etag });
+        state.save(&state_path)?;
+        progress.inc(size);
+    }
+    let action = format!("complete the upload of '{}'", file_path.display());
+    retry(&action, || {
+        multipart.complete(&state.key, &state.upload_id, &state.parts, &state.metadata)
+    })?;
+    progress.finish_and_clear();
+    remove_file(&state_path)?;
+    if state.is_temporary {
+        remove_file(file_path)?;
+    }
+    Ok(())
+}
+
+/// Runs the action until it succeeds, it waits longer and longer between attempts.
+///
+/// Only network errors are retried, others fail again anyway, such as denied credentials or an
+/// existing object.
+fn retry<T, F>(action: &str, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < MAX_ATTEMPTS && matches!(err, Error::Network(_)) => {
+                log::warn!(
+                    "failed to {} (attempt {}/{}), retry in {}s: {}",
+                    action,
+                    attempt,
+                    MAX_ATTEMPTS,
+                    backoff.as_secs(),
+                    err
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// The progress bar is only drawn on a terminal.
+fn progress_bar(total: u64) -> ProgressBar {
+    if !atty::is(atty::Stream::Stderr) {
+        return ProgressBar::hidden();
+    }
+    let progress = ProgressBar::new(total);
+    progress.set_style(ProgressStyle::default_bar().template(PROGRESS_TEMPLATE));
+    progress
+}
+
+fn ceil_div(a: u64, b: u64) -> u64 {
+    match a % b {
+        0 => a / b,
+        _ => a / b + 1,
+    }
+}
+
+fn state_path(file_path: &Path) -> PathBuf {
+    let mut path = file_path.as_os_str().to_owned();
+    path.push(format!(".{}", STATE_EXTENSION));
+    PathBuf::from(path)
+}
+
+/// The size and the modified time in seconds of the file.
+fn stat(file_path: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(file_path).map_err(|err| {
+        let msg = format!("failed to stat '{}' since {}", file_path.display(), err);
+        Error::Exec(msg)
+    })?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_secs())
+        .unwrap_or_default();
+    Ok((metadata.len(), modified))
+}
+
+impl State {
+    fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|err| {
+            let msg = format!("failed to read '{}' since {}", path.display(), err);
+            Error::Exec(msg)
+        })?;
+        serde_json::from_str(&content).map_err(|err| {
+            let msg = format!("failed to parse '{}' since {}", path.display(), err);
+            Error::Exec(msg)
+        })
+    }
+
+    /// Writes a new file then renames it, so the state is never half written.
+    fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self).map_err(|err| {
+            let msg = format!("failed to serialize the upload state since {}", err);
+            Error::Exec(msg)
+        })?;
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        fs::write(&tmp_path, content)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|err| {
+                let msg = format!("failed to write '{}' since {}", path.display(), err);
+                Error::Exec(msg)
+            })
+    }
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    fs::remove_file(path).map_err(|err| {
+        let msg = format!("failed to remove '{}' since {}", path.display(), err);
+        Error::Exec(msg)
+    })
+}